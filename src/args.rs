@@ -1,6 +1,10 @@
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
 use log::LevelFilter;
 
+use crate::format::LayoutFormat;
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 /// A tool for restoring your carefully-arranged window layout on your MacBook.
@@ -9,9 +13,28 @@ pub struct Args {
     #[arg(short, long, default_value = "info", global = true)]
     pub log_level: LevelFilter,
 
-    /// The path to the layout file.
-    #[arg(short, long, default_value = "~/.layout.yaml", global = true)]
-    pub path: String,
+    /// The path to your layout file. It's merged with the system (/etc/layout/layout.yaml), XDG
+    /// ($XDG_CONFIG_HOME/layout/layout.yaml) and per-host (e.g. ~/.layout.<hostname>.yaml) layout files, if they
+    /// exist; see `layout config paths`.
+    /// `~` and `$VARIABLES` in the path are expanded. [default: ~/.layout.yaml]
+    #[arg(short, long, env = "LAYOUT_FILE", global = true)]
+    path: Option<String>,
+
+    /// The profile (from the layout file's `profiles` section) to restore, or to save the current layout as.
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// When restoring, show which rules match each window (most specific first), and what they merge to.
+    #[arg(long, global = true)]
+    pub explain: bool,
+
+    /// Record every screen and window enumeration, and every window move, to the specified file.
+    #[arg(long, global = true, conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Run against a recording made with --record, instead of the real window system.
+    #[arg(long, global = true)]
+    pub replay: Option<PathBuf>,
 
     #[command(subcommand)]
     command: Option<Command>,
@@ -22,10 +45,62 @@ pub enum Command {
     /// Restore the layout. This is the default action.
     Restore,
     /// "Save" (print to stdout) the current window layout.
-    Save,
+    /// With --profile, the layout file is updated instead: the profile is added, or replaced if it already exists.
+    /// (Note that comments in the layout file will not survive this.)
+    Save {
+        /// The format to print the layout in. With --profile, the layout file keeps its own format.
+        #[arg(long, value_enum, default_value_t = LayoutFormat::Yaml)]
+        format: LayoutFormat,
+        /// Save positions as fractions of the screen (!Frac) rather than in pixels (!Pos), so that they scale to
+        /// screens of other sizes.
+        #[arg(long)]
+        relative: bool,
+    },
+    /// Check the layout files for problems, without touching any windows. Exits with a non-zero status if any are
+    /// found.
+    Validate {
+        /// How to report the problems.
+        #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+    /// Print the JSON Schema for layout files, e.g. for editor completion and validation with yaml-language-server.
+    Schema,
+    /// Show information about the layout files.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum ConfigCommand {
+    /// Show every place a layout file is looked for, and which ones were found and loaded. The files are merged
+    /// in the order shown, each one taking precedence over the ones before it.
+    Paths,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReportFormat {
+    /// One line per problem, like a compiler's.
+    Text,
+    /// A JSON array of problems, e.g. for a pre-commit hook.
+    Json,
 }
 
+// The user's own layout file, unless --path or LAYOUT_FILE says otherwise.
+const DEFAULT_LAYOUT_FILE: &str = "~/.layout.yaml";
+
 impl Args {
+    /// The path to the user's own layout file, before `~` and variables are expanded.
+    pub fn path(&self) -> &str {
+        self.path.as_deref().unwrap_or(DEFAULT_LAYOUT_FILE)
+    }
+
+    /// Whether the layout file was specified (with --path or LAYOUT_FILE), rather than being the default.
+    pub fn path_is_explicit(&self) -> bool {
+        self.path.is_some()
+    }
+
     /// "restore" is the default command if none are specified.
     pub fn command(&self) -> Command {
         self.command.clone().unwrap_or(Command::Restore)
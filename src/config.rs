@@ -0,0 +1,108 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+// The system-wide layout file, e.g. for baseline rules that a team installs on every machine.
+const SYSTEM_LAYOUT_FILE: &str = "/etc/layout/layout.yaml";
+
+/// Where a layout file comes from. The layers are merged in this order, each taking precedence over the ones before
+/// it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Layer {
+    System,
+    Xdg,
+    User,
+    Host,
+}
+
+impl Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Layer::System => "system",
+            Layer::Xdg => "xdg",
+            Layer::User => "user",
+            Layer::Host => "host",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LayoutFile {
+    pub layer: Layer,
+    pub path: PathBuf,
+    // Whether it's an error for the file not to exist.
+    pub required: bool,
+}
+
+/// Returns every place a layout file is looked for, lowest precedence first. The files don't have to exist.
+/// <br>`user_path` is the user's own layout file (i.e. `--path`). The per-host file sits next to it, with the
+/// host name before the extension, e.g. `~/.layout.my-laptop.yaml`. `user_required` says whether the user's file
+/// has to exist.
+pub fn layout_files(user_path: &Path, user_required: bool) -> Vec<LayoutFile> {
+    let mut files = vec![LayoutFile {
+        layer: Layer::System,
+        path: PathBuf::from(SYSTEM_LAYOUT_FILE),
+        required: false,
+    }];
+
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(config_home) = config_home {
+        files.push(LayoutFile {
+            layer: Layer::Xdg,
+            path: config_home.join("layout").join("layout.yaml"),
+            required: false,
+        });
+    }
+
+    files.push(LayoutFile {
+        layer: Layer::User,
+        path: user_path.to_path_buf(),
+        required: user_required,
+    });
+
+    if let Some(host_name) = host_name() {
+        files.push(LayoutFile {
+            layer: Layer::Host,
+            path: host_path(user_path, &host_name),
+            required: false,
+        });
+    }
+
+    files
+}
+
+/// Returns the short (i.e. unqualified) host name.
+fn host_name() -> Option<String> {
+    let host_name = gethostname::gethostname().into_string().ok()?;
+    let short_name = host_name.split('.').next().unwrap_or_default();
+
+    (!short_name.is_empty()).then(|| short_name.to_string())
+}
+
+/// Inserts the host name before the file's extension, if it has one.
+fn host_path(user_path: &Path, host_name: &str) -> PathBuf {
+    let file_name = match (user_path.file_stem(), user_path.extension()) {
+        (Some(stem), Some(extension)) => {
+            format!("{}.{}.{}", stem.to_string_lossy(), host_name, extension.to_string_lossy())
+        }
+        _ => format!("{}.{}", user_path.file_name().unwrap_or_default().to_string_lossy(), host_name),
+    };
+
+    user_path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_file_sits_next_to_the_user_file() {
+        assert_eq!(
+            host_path(Path::new("/home/me/.layout.yaml"), "my-laptop"),
+            PathBuf::from("/home/me/.layout.my-laptop.yaml")
+        );
+        assert_eq!(host_path(Path::new("/home/me/layout"), "my-laptop"), PathBuf::from("/home/me/layout.my-laptop"));
+    }
+}
@@ -7,21 +7,18 @@ use core_foundation_sys::string::CFStringRef;
 
 #[allow(unused)]
 pub fn get_dict_keys(dict: &CFDictionary) -> Vec<String> {
-    let (keys, values) = dict.get_keys_and_values();
-    let length = dict.len();
+    let (keys, _values) = dict.get_keys_and_values();
 
-    let mut results = Vec::new();
-    for i in 0..length {
-        results.push(unsafe { CFString::wrap_under_get_rule(keys[i] as CFStringRef) }.to_string());
-    }
-    results
+    keys.iter()
+        .map(|key| unsafe { CFString::wrap_under_get_rule(*key as CFStringRef) }.to_string())
+        .collect()
 }
 
 pub fn get_string_from_dict(dict: &CFDictionary, key: &str) -> String {
     let key = CFString::new(key);
     match dict.find(key.to_void()) {
         Some(value) => unsafe { CFString::wrap_under_get_rule(*value as CFStringRef) }.to_string(),
-        None => return String::new(),
+        None => String::new(),
     }
 }
 
@@ -43,9 +40,6 @@ where
 
 pub fn get_dict_from_dict(dict: &CFDictionary, key: &str) -> Option<CFDictionary> {
     let key = CFString::new(key);
-    let value = match dict.find(key.to_void()) {
-        Some(n) => n,
-        None => return None,
-    };
+    let value = dict.find(key.to_void())?;
     Some(unsafe { CFDictionary::wrap_under_get_rule(*value as CFDictionaryRef) })
 }
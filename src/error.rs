@@ -0,0 +1,158 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+/// The ways in which Layout can fail. Each has its own process exit code (see `LayoutError::exit_code`), so that
+/// scripts can tell them apart.
+#[derive(Debug, Error)]
+pub enum LayoutError {
+    // A layout file that should be there, isn't.
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("Failed to read {}: {source}", .path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to write {}: {source}", .path.display())]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    // A file that isn't valid YAML (or JSON, or TOML), or doesn't describe a layout (e.g. `pos: !Pos 1,2,3`).
+    #[error("Failed to parse {}, line {line}, column {column}: {message}", .path.display())]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    // A layout that parses, but doesn't make sense, e.g. an undefined alias or an unknown profile.
+    #[error("{0}")]
+    Invalid(String),
+
+    // Layout hasn't been given the permissions it needs to see (or move) windows, or to access a file.
+    #[error("{0}")]
+    Permission(String),
+
+    #[error("No screens were found")]
+    NoScreens,
+
+    // The window system failed, e.g. it couldn't be connected to.
+    #[error("{0}")]
+    Backend(String),
+}
+
+impl LayoutError {
+    /// Returns the process exit code for the error. 1 is left for anything unexpected.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LayoutError::NotFound(_) => 2,
+            LayoutError::Read { .. } | LayoutError::Write { .. } => 3,
+            LayoutError::Parse { .. } => 4,
+            LayoutError::Invalid(_) => 5,
+            LayoutError::Permission(_) => 6,
+            LayoutError::NoScreens => 7,
+            LayoutError::Backend(_) => 8,
+        }
+    }
+
+    /// Rebuilds an error from its exit code and message, e.g. when replaying a recording (see `RecordedError`). Only
+    /// the kinds of error that are no more than a message can be rebuilt.
+    pub fn rebuild(exit_code: i32, message: String) -> Option<Self> {
+        match exit_code {
+            2 => Some(LayoutError::NotFound(message)),
+            5 => Some(LayoutError::Invalid(message)),
+            6 => Some(LayoutError::Permission(message)),
+            7 => Some(LayoutError::NoScreens),
+            8 => Some(LayoutError::Backend(message)),
+            _ => None,
+        }
+    }
+
+    /// Converts an error reading the specified file.
+    pub fn read(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        let path = path.into();
+        match source.kind() {
+            std::io::ErrorKind::PermissionDenied => {
+                LayoutError::Permission(format!("Not allowed to access {}: {}", path.display(), source))
+            }
+            _ => LayoutError::Read { path, source },
+        }
+    }
+
+    /// Converts an error writing the specified file.
+    pub fn write(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        let path = path.into();
+        match source.kind() {
+            std::io::ErrorKind::PermissionDenied => {
+                LayoutError::Permission(format!("Not allowed to write {}: {}", path.display(), source))
+            }
+            _ => LayoutError::Write { path, source },
+        }
+    }
+
+    /// Converts a YAML error for the specified file, keeping the location of the error.
+    pub fn parse(path: impl Into<PathBuf>, error: &serde_yaml::Error) -> Self {
+        let (line, column) = error
+            .location()
+            .map_or((0, 0), |location| (location.line(), location.column()));
+        // The location is also at the end of the message, where it's harder to spot.
+        let message = error.to_string();
+        let message = match message.rfind(" at line ") {
+            Some(index) if line > 0 => message[..index].to_string(),
+            _ => message,
+        };
+
+        LayoutError::Parse {
+            path: path.into(),
+            line,
+            column,
+            message,
+        }
+    }
+
+    /// Converts a JSON error for the specified file, keeping the location of the error.
+    pub fn parse_json(path: impl Into<PathBuf>, error: &serde_json::Error) -> Self {
+        let message = error.to_string();
+        let message = match message.rfind(" at line ") {
+            Some(index) if error.line() > 0 => message[..index].to_string(),
+            _ => message,
+        };
+
+        LayoutError::Parse {
+            path: path.into(),
+            line: error.line(),
+            column: error.column(),
+            message,
+        }
+    }
+
+    /// Converts a TOML error for the specified file. TOML errors only know where they are in the text, so that has to
+    /// be turned into a line and column.
+    pub fn parse_toml(path: impl Into<PathBuf>, text: &str, error: &toml::de::Error) -> Self {
+        let (line, column) = error.span().map_or((0, 0), |span| {
+            let before = &text[..span.start.min(text.len())];
+            let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+            (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+        });
+
+        LayoutError::Parse {
+            path: path.into(),
+            line,
+            column,
+            message: error.message().to_string(),
+        }
+    }
+}
+
+/// Returns the exit code for an error: the `LayoutError`'s own, if it is (or wraps) one.
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    error.downcast_ref::<LayoutError>().map_or(1, LayoutError::exit_code)
+}
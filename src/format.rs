@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::LayoutError;
+
+/// The formats a layout file can be written in. Tags like `!Left 0.5` only exist in YAML: in JSON and TOML, tagged
+/// values are written as single-entry maps instead, e.g. `{"Left": 0.5}`.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LayoutFormat {
+    /// YAML, e.g. `pos: !Left 0.5`.
+    #[default]
+    Yaml,
+    /// JSON, e.g. `"pos": {"Left": 0.5}`.
+    Json,
+    /// TOML, e.g. `pos = { Left = 0.5 }`.
+    Toml,
+}
+
+impl LayoutFormat {
+    /// Returns the format of the specified file, from its extension. Anything that isn't `.json` or `.toml` is YAML.
+    pub fn of(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("json") => LayoutFormat::Json,
+            Some("toml") => LayoutFormat::Toml,
+            _ => LayoutFormat::Yaml,
+        }
+    }
+
+    /// Parses the contents of the specified file.
+    pub fn parse<T: DeserializeOwned>(self, path: &Path, text: &str) -> anyhow::Result<T> {
+        Ok(match self {
+            LayoutFormat::Yaml => serde_yaml::from_str(text).map_err(|e| LayoutError::parse(path, &e))?,
+            LayoutFormat::Json => serde_json::from_str(text).map_err(|e| LayoutError::parse_json(path, &e))?,
+            LayoutFormat::Toml => toml::from_str(text).map_err(|e| LayoutError::parse_toml(path, text, &e))?,
+        })
+    }
+
+    pub fn to_string<T: Serialize>(self, value: &T) -> anyhow::Result<String> {
+        Ok(match self {
+            LayoutFormat::Yaml => serde_yaml::to_string(value)?,
+            LayoutFormat::Json => serde_json::to_string_pretty(value)?,
+            LayoutFormat::Toml => toml::to_string(value)?,
+        })
+    }
+}
+
+/// Reads and parses the specified file, in the format its extension says it's in.
+pub fn read_file<T: DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
+    let text = std::fs::read_to_string(path).map_err(|source| LayoutError::read(path, source))?;
+
+    LayoutFormat::of(path).parse(path, &text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_comes_from_the_extension() {
+        assert_eq!(LayoutFormat::of(Path::new("/home/me/.layout.yaml")), LayoutFormat::Yaml);
+        assert_eq!(LayoutFormat::of(Path::new("/home/me/layout.JSON")), LayoutFormat::Json);
+        assert_eq!(LayoutFormat::of(Path::new("/home/me/.layout.my-laptop.toml")), LayoutFormat::Toml);
+        assert_eq!(LayoutFormat::of(Path::new("/home/me/layout")), LayoutFormat::Yaml);
+    }
+
+    #[test]
+    fn parse_errors_have_a_location() {
+        let toml = "version = 2\n\n[[windows]]\nowner_name = \"Slack\"\npos = { Pos = \"1,2,3\" }\n";
+        let json = "{\n  \"version\": 2,\n  \"windows\": [\n    {\"owner_name\": \"Slack\", \"pos\": 5}\n  ]\n}";
+
+        for (format, text, expected_line) in [(LayoutFormat::Toml, toml, 5), (LayoutFormat::Json, json, 4)] {
+            let error = format
+                .parse::<crate::layout_types::Layout>(Path::new("layout"), text)
+                .unwrap_err();
+            match error.downcast_ref::<LayoutError>() {
+                Some(LayoutError::Parse { line, .. }) => assert_eq!(*line, expected_line, "{:?}: {:#}", format, error),
+                _ => panic!("Expected a parse error, got: {:#}", error),
+            }
+        }
+    }
+}
@@ -1,27 +1,344 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
 use std::fmt::Display;
+use std::path::{Path, PathBuf};
 
-use core_graphics_types::base::CGFloat;
-use core_graphics_types::geometry::{CGPoint, CGRect, CGSize};
 use log::debug;
 use regex::Regex;
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::de::value::{EnumAccessDeserializer, MapAccessDeserializer};
+use serde::de::{EnumAccess, IntoDeserializer, MapAccess, VariantAccess, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::layout_types::MaybeRegex::{Exact, RE};
+use crate::error::LayoutError;
+use crate::format::LayoutFormat;
+use crate::layout_types::MaybeRegex::{Alias, Exact, Glob, RE};
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// The current layout file version. Version 1 files (which have no `version`) treat plain strings that compile as a
+/// regex as an unanchored regex; from version 2 on, plain strings are exact matches.
+pub const LAYOUT_VERSION: u32 = 2;
+
+// The doc comments on the layout file types end up in the JSON Schema (see `layout schema`).
+/// A layout file.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Layout {
+    /// The layout file version. Files without one are version 1, where plain strings that compile as a regular
+    /// expression are (unanchored) regular expressions.
+    #[serde(default = "legacy_version")]
+    pub version: u32,
+    /// Other layout files to merge into this one. Relative paths are relative to this file's directory.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Owner and name patterns that rules can refer to as `$name`, e.g. `owner_name: $jetbrains`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, MaybeRegex>,
+    /// Positions that rules can refer to as `$name`, e.g. `pos: $ide_main`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty", deserialize_with = "deserialize_positions")]
+    #[schemars(schema_with = "positions_schema")]
+    pub positions: BTreeMap<String, WindowPos>,
+    /// The space to leave around windows whose position is computed, i.e. anything but `Pos`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gaps: Option<Gaps>,
+    /// Rules that apply no matter which profile is chosen.
+    #[serde(default)]
+    pub windows: Vec<WindowInfo>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    // The files this layout was loaded from: the file itself, followed by any it included, in order of precedence.
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+}
+
+/// A named set of window rules, e.g. one for when the laptop is docked and another for when it isn't.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Profile {
+    /// The screen configuration this profile is for. When no profile is specified, the profile whose screens best
+    /// match the current ones is chosen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screens: Option<ScreenSignature>,
     pub windows: Vec<WindowInfo>,
 }
 
+/// Tiling-style spacing for computed positions, in pixels.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Gaps {
+    /// Padding between the edges of the screen and the windows: it shrinks the area that positions are computed in.
+    #[serde(default)]
+    pub outer: i32,
+    /// The space between neighboring `Left`/`Right`/`Top`/`Bottom`, `Grid` and `Frac` windows.
+    #[serde(default)]
+    pub inner: i32,
+}
+
+/// The space that `WindowPos::to_absolute` leaves around a window: the layout file's gaps, and the rule's margin.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Spacing {
+    pub gaps: Gaps,
+    pub margin: i32,
+}
+
+/// Describes a screen configuration. Every part is optional; only the parts that are specified are compared.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ScreenSignature {
+    /// The number of screens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+    /// The screen resolutions, left to right.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resolutions: Vec<Size>,
+    /// The ids of specific monitors: the display ids on macOS, and ids derived from the output names (e.g. "DP-1") on
+    /// Linux.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ids: Vec<u32>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            version: LAYOUT_VERSION,
+            include: Vec::new(),
+            aliases: BTreeMap::new(),
+            positions: BTreeMap::new(),
+            gaps: None,
+            windows: Vec::new(),
+            profiles: BTreeMap::new(),
+            files: Vec::new(),
+        }
+    }
+}
+
+fn legacy_version() -> u32 {
+    1
+}
+
+// For error messages about a rule.
+fn in_file(source: &Option<PathBuf>) -> String {
+    match source {
+        Some(source) => format!(" (in {})", source.display()),
+        None => String::new(),
+    }
+}
+
+impl Layout {
+    /// Applies the rules of the file's version, once it's been loaded: in version 2 and up, plain strings are exact
+    /// matches even when they'd compile as a regex. Before version 2, `$name` isn't an alias, just a string that
+    /// starts with `$`.
+    pub fn apply_version(&mut self) {
+        let fix_up = match self.version {
+            version if version < 2 => MaybeRegex::downgraded,
+            _ => MaybeRegex::upgraded,
+        };
+
+        let profile_rules = self
+            .profiles
+            .values_mut()
+            .flat_map(|profile| profile.windows.iter_mut());
+        for rule in self.windows.iter_mut().chain(profile_rules) {
+            rule.owner_name = fix_up(&rule.owner_name);
+            rule.name = fix_up(&rule.name);
+        }
+        for alias in self.aliases.values_mut() {
+            *alias = fix_up(alias);
+        }
+    }
+
+    /// Returns the layout in the specified format. A plain string is a regex in a version 1 file, so exact matches are
+    /// always written as `!Exact` in one.
+    pub fn to_text(&self, format: LayoutFormat) -> anyhow::Result<String> {
+        EXPLICIT_EXACT.with(|explicit| explicit.set(self.version < 2));
+        let text = format.to_string(self);
+        EXPLICIT_EXACT.with(|explicit| explicit.set(false));
+        text
+    }
+
+    /// Records the file that every rule in this layout came from.
+    pub fn set_source(&mut self, source: &Path) {
+        let profile_rules = self
+            .profiles
+            .values_mut()
+            .flat_map(|profile| profile.windows.iter_mut());
+        for rule in self.windows.iter_mut().chain(profile_rules) {
+            rule.source = Some(source.to_path_buf());
+        }
+    }
+
+    /// Merges an included layout file into this one. Everything in this layout takes precedence over what's in the
+    /// included one: its rules come first (so they win ties, see `Layout::rules`), and its aliases, positions and
+    /// profile screen signatures replace any with the same name.
+    pub fn merge_included(&mut self, included: Layout) {
+        self.files.extend(included.files);
+        self.windows.extend(included.windows);
+        for (name, alias) in included.aliases {
+            self.aliases.entry(name).or_insert(alias);
+        }
+        for (name, position) in included.positions {
+            self.positions.entry(name).or_insert(position);
+        }
+        self.gaps = self.gaps.take().or(included.gaps);
+        for (name, included_profile) in included.profiles {
+            match self.profiles.get_mut(&name) {
+                Some(profile) => {
+                    profile.screens = profile.screens.take().or(included_profile.screens);
+                    profile.windows.extend(included_profile.windows);
+                }
+                None => {
+                    self.profiles.insert(name, included_profile);
+                }
+            }
+        }
+    }
+
+    /// Replaces every `$name` reference in the rules with the alias (or position) it refers to.
+    pub fn resolve_aliases(&mut self) -> anyhow::Result<()> {
+        if let Some((name, _)) = self.aliases.iter().find(|(_, alias)| matches!(alias, Alias(_))) {
+            return Err(LayoutError::Invalid(format!(
+                "The alias '{}' refers to another alias, which isn't supported",
+                name
+            ))
+            .into());
+        }
+
+        let profile_rules = self
+            .profiles
+            .values_mut()
+            .flat_map(|profile| profile.windows.iter_mut());
+        for rule in self.windows.iter_mut().chain(profile_rules) {
+            for pattern in [&mut rule.owner_name, &mut rule.name] {
+                if let Alias(name) = pattern {
+                    *pattern = self.aliases.get(name.as_str()).cloned().ok_or_else(|| {
+                        LayoutError::Invalid(format!(
+                            "'${}' is not defined in the aliases section{}",
+                            name,
+                            in_file(&rule.source)
+                        ))
+                    })?;
+                }
+            }
+            if let Some(WindowPos::Alias(name)) = &rule.pos {
+                rule.pos = Some(self.positions.get(name.as_str()).cloned().ok_or_else(|| {
+                    LayoutError::Invalid(format!(
+                        "'${}' is not defined in the positions section{}",
+                        name,
+                        in_file(&rule.source)
+                    ))
+                })?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the window rules for the specified profile (or for no profile), in the order they should be tried.
+    /// <br>The most specific rules come first (see `WindowInfo::specificity`). Between equally specific rules, the
+    /// profile's own rules come before the top-level rules (which apply to every profile), and otherwise the order
+    /// of the layout file is kept.
+    pub fn rules(&self, profile: Option<&str>) -> anyhow::Result<Vec<&WindowInfo>> {
+        let profile_rules = match profile {
+            Some(name) => match self.profiles.get(name) {
+                Some(profile) => profile.windows.iter().collect(),
+                None => {
+                    return Err(LayoutError::Invalid(format!(
+                        "There is no profile named '{}' in the layout file",
+                        name
+                    ))
+                    .into())
+                }
+            },
+            None => Vec::new(),
+        };
+
+        let mut rules: Vec<&WindowInfo> = profile_rules.into_iter().chain(self.windows.iter()).collect();
+        // A stable sort, so ties keep their order.
+        rules.sort_by_key(|rule| Reverse(rule.specificity()));
+        Ok(rules)
+    }
+
+    /// Returns the name of the profile whose screen signature best matches the specified screens, if any do.
+    pub fn select_profile(&self, screens: &[ScreenInfo]) -> Option<&str> {
+        let mut best: Option<(&str, u32)> = None;
+
+        for (name, profile) in &self.profiles {
+            let score = match profile.screens.as_ref().and_then(|signature| signature.score(screens)) {
+                Some(score) => score,
+                None => continue,
+            };
+            debug!("Profile '{}' matches the current screens with a score of {}", name, score);
+
+            // Ties go to the first profile, alphabetically.
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((name, score));
+            }
+        }
+
+        best.map(|(name, _)| name)
+    }
+}
+
+impl ScreenSignature {
+    /// Returns the signature of the specified screens.
+    pub fn of(screens: &[ScreenInfo]) -> Self {
+        ScreenSignature {
+            count: Some(screens.len()),
+            resolutions: screens.iter().map(|screen| Size::from(&screen.frame)).collect(),
+            ids: screens.iter().map(|screen| screen.screen_id).collect(),
+        }
+    }
+
+    /// Returns how well the specified screens (ordered left to right) match this signature, or `None` if they
+    /// don't match at all. The count has to match exactly (if specified). Resolutions and ids score a point for
+    /// each one that matches (ids more so, since they identify a specific monitor), but at least one has to.
+    pub fn score(&self, screens: &[ScreenInfo]) -> Option<u32> {
+        let mut score = 0;
+
+        if let Some(count) = self.count {
+            if count != screens.len() {
+                return None;
+            }
+            score += 1;
+        }
+
+        if !self.resolutions.is_empty() {
+            let matches = self
+                .resolutions
+                .iter()
+                .zip(screens)
+                .filter(|(resolution, screen)| **resolution == Size::from(&screen.frame))
+                .count() as u32;
+            if matches == 0 {
+                return None;
+            }
+            score += 2 * matches;
+        }
+
+        if !self.ids.is_empty() {
+            let matches = self
+                .ids
+                .iter()
+                .filter(|id| screens.iter().any(|screen| screen.screen_id == **id))
+                .count() as u32;
+            if matches == 0 {
+                return None;
+            }
+            score += 3 * matches;
+        }
+
+        Some(score)
+    }
+}
+
 #[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ScreenInfo {
     pub frame: Rect,
     pub screen_id: u32,
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// A window rule. (Or, when saving the current layout, a window.)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct WindowInfo {
+    /// The application's name.
     pub owner_name: MaybeRegex,
+    /// The window's title.
     pub name: MaybeRegex,
     // For our purposes we want to move every window that has the same Owner Name + Name to the same position. So
     // we need to keep track of the process_id, window_id and position of all matching windows.
@@ -29,74 +346,440 @@ pub struct WindowInfo {
     // the `skip_serializing, skip_deserializing`.
     #[serde(skip_serializing, skip_deserializing)]
     pub matching_windows: Vec<MatchingWindowInfo>,
-    pub screen_num: usize,
-    pub pos: WindowPos,
+    // The layout file the rule came from, which can be an included file.
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+    // A rule can leave out the screen number or position (or both), to have them come from other, less specific,
+    // rules that match the same window. See `WindowInfo::merge`.
+    /// The left-to-right position of the screen: 1 is the left-most.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 1))]
+    pub screen_num: Option<usize>,
+    /// Where on the screen the window goes, or a `$name` from the positions section.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_pos",
+        deserialize_with = "deserialize_pos"
+    )]
+    #[schemars(schema_with = "pos_schema")]
+    pub pos: Option<WindowPos>,
+    /// The size of the screen that a `Pos` was saved on. When the window is restored to a screen of a different
+    /// size, the `Pos` is scaled to fit it (see `scale`). `layout save` fills this in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screen_size: Option<Size>,
+    /// How a `Pos` is fitted to a screen that isn't the size it was saved on. The default is `proportional`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<Scale>,
+    /// Space to leave on every side of the window, in pixels, on top of the layout file's `gaps`. Like the gaps, it
+    /// doesn't apply to `Pos`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub margin: Option<i32>,
+    /// Rules with a higher priority are tried first, regardless of how specific they are. The default is 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+}
+
+/// How specific a rule is. More specific rules are tried first; the fields are compared in order.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Specificity {
+    pub priority: i32,
+    // The number of exact (as opposed to pattern) fields: 0, 1 or 2.
+    pub exact: usize,
+    // The number of literal (i.e. required, non-wildcard) characters in the owner and name patterns.
+    pub literal: usize,
+}
+
+impl Display for Specificity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "priority {}, {} exact, {} literal chars", self.priority, self.exact, self.literal)
+    }
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// How a `Pos` that was saved on a screen of one size (see `WindowInfo::screen_size`) is fitted to a screen of
+/// another size.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scale {
+    /// The position and size are used as they are.
+    None,
+    /// The position and size are scaled along with the screen.
+    #[default]
+    Proportional,
+    /// The window keeps its size, and its center is moved proportionally.
+    KeepSize,
+}
+
+/// A window position. In a YAML layout file, these are written as tags, e.g. `!Maxed` or `!Left 0.5`. In JSON and TOML
+/// they're written as a single-entry map, e.g. `{"Left": 0.5}`, or just the name for `"Maxed"`. snake_case names work
+/// too, e.g. `{left: 0.5}` or `{bottom_right: "400,143"}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[schemars(transform = with_snake_case_names)]
 pub enum WindowPos {
+    /// Fills the screen.
     #[default]
+    #[serde(alias = "maxed")]
     Maxed,
+    /// An exact position and size on the screen. Negative x and y are relative to the right and bottom edges.
+    #[serde(alias = "pos")]
     Pos(Rect),
-    Left(f32),
-    Right(f32),
-    Top(f32),
-    Bottom(f32),
+    /// The left part of the screen: the fraction is of the screen's width.
+    #[serde(alias = "left")]
+    Left(#[schemars(range(min = 0.0, max = 1.0))] f32),
+    /// The right part of the screen: the fraction is of the screen's width.
+    #[serde(alias = "right")]
+    Right(#[schemars(range(min = 0.0, max = 1.0))] f32),
+    /// The top part of the screen: the fraction is of the screen's height.
+    #[serde(alias = "top")]
+    Top(#[schemars(range(min = 0.0, max = 1.0))] f32),
+    /// The bottom part of the screen: the fraction is of the screen's height.
+    #[serde(alias = "bottom")]
+    Bottom(#[schemars(range(min = 0.0, max = 1.0))] f32),
+    /// One or more cells of a grid that divides the screen into equal columns and rows.
+    #[serde(alias = "grid")]
+    Grid(Grid),
+    /// A position and size as fractions of the screen's width and height, so that it scales with the screen.
+    #[serde(alias = "frac")]
+    Frac(FracRect),
+    /// A window of the specified size in the center of the screen, moved by the offset (if any).
+    #[serde(alias = "center")]
+    Center(Anchored),
+    /// A window of the specified size in the top-left corner of the screen, the offset (if any) away from it.
+    #[serde(alias = "top_left")]
+    TopLeft(Anchored),
+    /// A window of the specified size in the top-right corner of the screen, the offset (if any) away from it.
+    #[serde(alias = "top_right")]
+    TopRight(Anchored),
+    /// A window of the specified size in the bottom-left corner of the screen, the offset (if any) away from it.
+    #[serde(alias = "bottom_left")]
+    BottomLeft(Anchored),
+    /// A window of the specified size in the bottom-right corner of the screen, the offset (if any) away from it.
+    #[serde(alias = "bottom_right")]
+    BottomRight(Anchored),
+    // A reference to one of the layout file's `positions`, written as `$name`. Only exists until the layout file
+    // has been loaded (see `Layout::resolve_aliases`).
+    #[serde(skip)]
+    Alias(String),
+}
+
+// Rules can refer to a named position as `pos: $name`, which the derived (de)serializer doesn't know about.
+fn serialize_pos<S>(pos: &Option<WindowPos>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match pos {
+        Some(WindowPos::Alias(name)) => serializer.serialize_str(&format!("${}", name)),
+        pos => pos.serialize(serializer),
+    }
+}
+
+// A rule's position can also be a `$name`. And yaml-language-server (which is what the schema is mostly for) only
+// sees the value of a tag, e.g. the 0.5 of `!Left 0.5`, so tagged values are accepted as they are.
+fn pos_schema(generator: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+        "anyOf": [
+            generator.subschema_for::<WindowPos>(),
+            generator.subschema_for::<Grid>(),
+            {
+                "description": "A $name from the positions section, or a tagged value, e.g. !Maxed or !Left 0.5",
+                "type": ["string", "number", "null"]
+            }
+        ]
+    })
+}
+
+// The snake_case names that serde accepts (see `WindowPos` and `MaybeRegexVisitor`) have to be in the schema too, so
+// every alternative that's named after a variant is repeated under its snake_case name.
+fn with_snake_case_names(schema: &mut Schema) {
+    for key in ["oneOf", "anyOf"] {
+        if let Some(serde_json::Value::Array(alternatives)) = schema.get_mut(key) {
+            let renamed: Vec<_> = alternatives.iter().filter_map(snake_case_alternative).collect();
+            alternatives.extend(renamed);
+        }
+    }
+}
+
+// E.g. `{"const": "Maxed"}` as `{"const": "maxed"}`, or `{"properties": {"BottomRight": ...}}` as
+// `{"properties": {"bottom_right": ...}}`.
+fn snake_case_alternative(alternative: &serde_json::Value) -> Option<serde_json::Value> {
+    let mut renamed = alternative.clone();
+    if let Some(name) = alternative.get("const").and_then(|name| name.as_str()) {
+        renamed["const"] = snake_case(name).into();
+        return Some(renamed);
+    }
+
+    let name = alternative.get("required")?.as_array()?.first()?.as_str()?;
+    let value = renamed.get_mut("properties")?.as_object_mut()?.remove(name)?;
+    renamed["properties"][snake_case(name)] = value;
+    renamed["required"] = serde_json::json!([snake_case(name)]);
+    Some(renamed)
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (index, c) in name.char_indices() {
+        if c.is_uppercase() && index > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+fn positions_schema(generator: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+        "type": "object",
+        "additionalProperties": pos_schema(generator)
+    })
+}
+
+// A position that's deserialized by `WindowPosVisitor`, i.e. that can be written as a map or as a `$name`.
+struct AnyPos(WindowPos);
+
+impl<'de> Deserialize<'de> for AnyPos {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(WindowPosVisitor).map(AnyPos)
+    }
+}
+
+// An explicit `pos: null` (which the schema allows, see `pos_schema`) is the same as leaving the position out.
+fn deserialize_pos<'de, D>(deserializer: D) -> Result<Option<WindowPos>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<AnyPos>::deserialize(deserializer)?.map(|AnyPos(pos)| pos))
+}
+
+// Named positions can be written as maps too (see `WindowPosVisitor::visit_map`), which the derived deserializer
+// doesn't accept in YAML. But they can't refer to other named positions.
+fn deserialize_positions<'de, D>(deserializer: D) -> Result<BTreeMap<String, WindowPos>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct Position(WindowPos);
+
+    impl<'de> Deserialize<'de> for Position {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            match AnyPos::deserialize(deserializer)? {
+                AnyPos(WindowPos::Alias(name)) => {
+                    Err(serde::de::Error::custom(format!("'${}': a named position can't refer to another one", name)))
+                }
+                AnyPos(pos) => Ok(Position(pos)),
+            }
+        }
+    }
+
+    let positions = BTreeMap::<String, Position>::deserialize(deserializer)?;
+    Ok(positions.into_iter().map(|(name, Position(pos))| (name, pos)).collect())
+}
+
+struct WindowPosVisitor;
+
+impl<'de> Visitor<'de> for WindowPosVisitor {
+    type Value = WindowPos;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a position such as !Maxed or !Pos x,y,w,h, or a $name from the positions section")
+    }
+
+    fn visit_str<E>(self, text_val: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        match text_val.strip_prefix('$') {
+            Some(name) => Ok(WindowPos::Alias(name.to_string())),
+            None => WindowPos::deserialize(text_val.into_deserializer()),
+        }
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        WindowPos::deserialize(EnumAccessDeserializer::new(data))
+    }
+
+    // E.g. `{left: 0.5}`, for formats without tags.
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        WindowPos::deserialize(MapAccessDeserializer::new(map))
+    }
 }
 
 impl WindowPos {
-    pub fn to_absolute(&self, screen: &ScreenInfo) -> Rect {
+    /// Returns the position's rect on the specified screen, in absolute coordinates. Every position but `Pos` is
+    /// computed inside the screen minus the outer gap, then has half of the inner gap taken off each edge that faces a
+    /// neighbor (for the positions that tile the screen), and finally the margin taken off every edge.
+    pub fn to_absolute(&self, screen: &ScreenInfo, spacing: Spacing) -> Rect {
+        // An exact position is used as it is, gaps or not.
+        if let WindowPos::Pos(_) = self {
+            return self.place(&screen.frame);
+        }
+        let area = screen.frame.inset(spacing.gaps.outer);
+        let mut rect = self.place(&area);
+        if matches!(
+            self,
+            WindowPos::Left(_)
+                | WindowPos::Right(_)
+                | WindowPos::Top(_)
+                | WindowPos::Bottom(_)
+                | WindowPos::Grid(_)
+                | WindowPos::Frac(_)
+        ) {
+            rect = rect.inset_inner_edges(&area, spacing.gaps.inner);
+        }
+        rect.inset(spacing.margin)
+    }
+
+    // Computes the position's rect inside `area`: the screen's frame, less the outer gap for anything but a `Pos`.
+    fn place(&self, area: &Rect) -> Rect {
         match self {
             WindowPos::Maxed => Rect {
-                x: screen.frame.x,
-                y: screen.frame.y,
-                w: screen.frame.w,
-                h: screen.frame.h,
+                x: area.x,
+                y: area.y,
+                w: area.w,
+                h: area.h,
             },
             WindowPos::Pos(rect) => {
-                let x = if rect.x < 0 { screen.frame.w + rect.x } else { rect.x };
-                let y = if rect.y < 0 { screen.frame.h + rect.y } else { rect.y };
+                let x = if rect.x < 0 { area.w + rect.x } else { rect.x };
+                let y = if rect.y < 0 { area.h + rect.y } else { rect.y };
                 Rect {
-                    x: x + screen.frame.x,
-                    y: y + screen.frame.y,
+                    x: x + area.x,
+                    y: y + area.y,
                     w: rect.w,
                     h: rect.h,
                 }
             }
             WindowPos::Left(fraction) => Rect {
-                x: screen.frame.x,
-                y: screen.frame.y,
-                w: (screen.frame.w as f32 * fraction) as i32,
-                h: screen.frame.h,
+                x: area.x,
+                y: area.y,
+                w: (area.w as f32 * fraction) as i32,
+                h: area.h,
             },
             WindowPos::Right(fraction) => {
-                let w = (screen.frame.w as f32 * fraction) as i32;
+                let w = (area.w as f32 * fraction) as i32;
                 Rect {
-                    x: screen.frame.x + screen.frame.w - w,
-                    y: screen.frame.y,
+                    x: area.x + area.w - w,
+                    y: area.y,
                     w,
-                    h: screen.frame.h,
+                    h: area.h,
                 }
             }
             WindowPos::Top(fraction) => Rect {
-                x: screen.frame.x,
-                y: screen.frame.y,
-                w: screen.frame.w,
-                h: (screen.frame.h as f32 * fraction) as i32,
+                x: area.x,
+                y: area.y,
+                w: area.w,
+                h: (area.h as f32 * fraction) as i32,
             },
             WindowPos::Bottom(fraction) => {
-                let h = (screen.frame.h as f32 * fraction) as i32;
+                let h = (area.h as f32 * fraction) as i32;
                 Rect {
-                    x: screen.frame.x,
-                    y: screen.frame.y + screen.frame.h - h,
-                    w: screen.frame.w,
+                    x: area.x,
+                    y: area.y + area.h - h,
+                    w: area.w,
                     h,
                 }
             }
+            WindowPos::Grid(grid) => {
+                let (x, w) = Grid::span(area.x, area.w, grid.columns, grid.column, grid.column_span);
+                let (y, h) = Grid::span(area.y, area.h, grid.rows, grid.row, grid.row_span);
+                Rect { x, y, w, h }
+            }
+            WindowPos::Frac(frac) => {
+                // As with grids, the edges are rounded rather than the size.
+                let (x, w) = FracRect::span(area.x, area.w, frac.x, frac.w);
+                let (y, h) = FracRect::span(area.y, area.h, frac.y, frac.h);
+                Rect { x, y, w, h }
+            }
+            WindowPos::Center(anchored)
+            | WindowPos::TopLeft(anchored)
+            | WindowPos::TopRight(anchored)
+            | WindowPos::BottomLeft(anchored)
+            | WindowPos::BottomRight(anchored) => {
+                let w = anchored.w.to_pixels(area.w);
+                let h = anchored.h.to_pixels(area.h);
+                // The offset is away from the corner, i.e. towards the center of the screen.
+                let dx = anchored.dx.to_pixels(area.w);
+                let dy = anchored.dy.to_pixels(area.h);
+                let x = match self {
+                    WindowPos::TopLeft(_) | WindowPos::BottomLeft(_) => area.x + dx,
+                    WindowPos::TopRight(_) | WindowPos::BottomRight(_) => area.x + area.w - w - dx,
+                    _ => area.x + (area.w - w) / 2 + dx,
+                };
+                let y = match self {
+                    WindowPos::TopLeft(_) | WindowPos::TopRight(_) => area.y + dy,
+                    WindowPos::BottomLeft(_) | WindowPos::BottomRight(_) => area.y + area.h - h - dy,
+                    _ => area.y + (area.h - h) / 2 + dy,
+                };
+                Rect { x, y, w, h }
+            }
+            WindowPos::Alias(name) => unreachable!("Position '${}' should have been resolved on load", name),
         }
     }
 
-    pub fn to_relative(&self, screens: &Vec<ScreenInfo>) -> (usize, Rect) {
+    /// Fits a `Pos` that was saved on a screen of the `reference` size to the specified screen, the way `scale` says
+    /// to. Any other position already fits every screen, and is returned as it is.
+    pub fn scaled(&self, reference: Size, screen: &ScreenInfo, scale: Scale) -> WindowPos {
+        let rect = match self {
+            WindowPos::Pos(rect) if reference.w > 0 && reference.h > 0 && reference != Size::from(&screen.frame) => {
+                rect
+            }
+            _ => return self.clone(),
+        };
+        let x_ratio = screen.frame.w as f64 / reference.w as f64;
+        let y_ratio = screen.frame.h as f64 / reference.h as f64;
+        let scale_by = |value: i32, ratio: f64| (value as f64 * ratio).round() as i32;
+        // Negative coordinates are relative to the right and bottom edges, which this works for, too.
+        let keep_size = |offset: i32, length: i32, ratio: f64| {
+            (((offset as f64 + length as f64 / 2.0) * ratio) - length as f64 / 2.0).round() as i32
+        };
+
+        WindowPos::Pos(match scale {
+            Scale::None => rect.clone(),
+            Scale::Proportional => Rect {
+                x: scale_by(rect.x, x_ratio),
+                y: scale_by(rect.y, y_ratio),
+                w: scale_by(rect.w, x_ratio),
+                h: scale_by(rect.h, y_ratio),
+            },
+            Scale::KeepSize => Rect {
+                x: keep_size(rect.x, rect.w, x_ratio),
+                y: keep_size(rect.y, rect.h, y_ratio),
+                w: rect.w,
+                h: rect.h,
+            },
+        })
+    }
+
+    /// Converts a `Pos` (relative to the screen) into a `Frac` of the screen, for `layout save --relative`. Any other
+    /// position is returned as it is.
+    pub fn to_fraction(&self, screen: &ScreenInfo) -> WindowPos {
+        match self {
+            WindowPos::Pos(rect) if screen.frame.w > 0 && screen.frame.h > 0 => {
+                let fraction = |value: i32, length: i32| {
+                    // Four decimal places are within a pixel, even on a 4K screen.
+                    (value as f32 / length as f32 * 10000.0).round() / 10000.0
+                };
+                WindowPos::Frac(FracRect {
+                    x: fraction(rect.x, screen.frame.w),
+                    y: fraction(rect.y, screen.frame.h),
+                    w: fraction(rect.w, screen.frame.w),
+                    h: fraction(rect.h, screen.frame.h),
+                })
+            }
+            pos => pos.clone(),
+        }
+    }
+
+    pub fn to_relative(&self, screens: &[ScreenInfo]) -> (usize, Rect) {
         match self {
             WindowPos::Pos(rect) => {
                 for (index, screen) in screens.iter().enumerate() {
@@ -138,10 +821,142 @@ pub struct Rect {
     pub h: i32,
 }
 
-#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Size {
+    pub w: i32,
+    pub h: i32,
+}
+
+/// A rect whose position and size are fractions of the screen's width and height, e.g. `0.25,0,0.5,1` is the middle
+/// half of the screen, top to bottom.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FracRect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl FracRect {
+    /// Returns the start and length of the part of a screen dimension (that starts at `start` and is `length` long)
+    /// that starts at the fraction `offset`, and is the fraction `size` long.
+    fn span(start: i32, length: i32, offset: f32, size: f32) -> (i32, i32) {
+        let edge = |fraction: f32| (length as f32 * fraction).round() as i32;
+
+        (start + edge(offset), edge(offset + size) - edge(offset))
+    }
+}
+
+/// A length in pixels or, when it's written with a decimal point (e.g. `0.5`), as a fraction of the screen's width or
+/// height.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Length {
+    Pixels(i32),
+    Fraction(f32),
+}
+
+impl Length {
+    /// Returns the length in pixels, on a screen whose width (or height) is `screen_length`.
+    pub fn to_pixels(self, screen_length: i32) -> i32 {
+        match self {
+            Length::Pixels(pixels) => pixels,
+            Length::Fraction(fraction) => (screen_length as f32 * fraction).round() as i32,
+        }
+    }
+}
+
+impl Default for Length {
+    fn default() -> Self {
+        Length::Pixels(0)
+    }
+}
+
+impl Display for Length {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Length::Pixels(pixels) => write!(f, "{}", pixels),
+            // Debug always includes the decimal point, e.g. 1.0 rather than 1, which would be a pixel.
+            Length::Fraction(fraction) => write!(f, "{:?}", fraction),
+        }
+    }
+}
+
+impl std::str::FromStr for Length {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        let length = match text.contains('.') {
+            true => text.parse().map(Length::Fraction).ok(),
+            false => text.parse().map(Length::Pixels).ok(),
+        };
+        length.ok_or_else(|| format!("Invalid length '{}', expected pixels (e.g. 400) or a fraction (e.g. 0.5)", text))
+    }
+}
+
+/// The size of a window that's anchored to the center or a corner of the screen, and how far it's offset from there:
+/// `w,h` or `w,h,dx,dy`, e.g. `400,143,50,57`. Each can be in pixels, or a fraction of the screen (e.g. `0.5`).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Anchored {
+    pub w: Length,
+    pub h: Length,
+    pub dx: Length,
+    pub dy: Length,
+}
+
+/// A block of cells in a grid that divides the screen into equal columns and rows, e.g.
+/// `!Grid {columns: 3, rows: 2, column: 2, row: 1, row_span: 2}` is the whole middle third of the screen.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Grid {
+    /// The number of columns the screen is divided into.
+    #[schemars(range(min = 1))]
+    pub columns: u32,
+    /// The number of rows the screen is divided into.
+    #[schemars(range(min = 1))]
+    pub rows: u32,
+    /// The window's (left-most) column: 1 is the left-most.
+    #[schemars(range(min = 1))]
+    pub column: u32,
+    /// The window's (top-most) row: 1 is the top.
+    #[schemars(range(min = 1))]
+    pub row: u32,
+    /// The number of columns the window spans. The default is 1.
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    #[schemars(range(min = 1))]
+    pub column_span: u32,
+    /// The number of rows the window spans. The default is 1.
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    #[schemars(range(min = 1))]
+    pub row_span: u32,
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn is_one(value: &u32) -> bool {
+    *value == 1
+}
+
+impl Grid {
+    /// Returns the start and length of `span` cells, starting at `cell` (from 1), when a screen dimension that starts
+    /// at `start` and is `length` long is divided into `cells` cells. The cells' edges are rounded, rather than their
+    /// sizes, so that neighboring cells meet exactly.
+    /// <br>A grid that doesn't make sense (see `layout validate`) is clamped to one that does, rather than crashing.
+    fn span(start: i32, length: i32, cells: u32, cell: u32, span: u32) -> (i32, i32) {
+        let cells = cells.max(1) as i64;
+        let first = (cell.max(1) as i64 - 1).min(cells - 1);
+        let last = (first + span.max(1) as i64).min(cells);
+        let edge = |index: i64| start + (length as i64 * index / cells) as i32;
+
+        (edge(first), edge(last) - edge(first))
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct MatchingWindowInfo {
     pub process_id: i32,
-    pub window_id: u32,
+    pub window_id: u64,
     // We also store a copy of the window position here, because in the "save" case we'll just save a single
     // position to the output file, but in the "restore" case we need to know the position of each window with
     // the same owner & window names.
@@ -150,23 +965,238 @@ pub struct MatchingWindowInfo {
 }
 
 impl WindowInfo {
-    pub fn matches(&self, other: &Self) -> bool {
-        (self.owner_name.matches(&other.owner_name.to_string()) && self.name.matches(&other.name.to_string()))
-            || (other.owner_name.matches(&self.owner_name.to_string()) && other.name.matches(&self.name.to_string()))
+    /// Returns true if this rule matches the window.
+    /// <br>Only the rule's owner and name are patterns: the window's are taken literally, no matter what characters
+    /// they contain.
+    pub fn matches(&self, window: &Self) -> bool {
+        self.owner_name.matches(&window.owner_name.to_string()) && self.name.matches(&window.name.to_string())
+    }
+
+    /// Merges all of the rules that match the window, CSS-style: each property comes from the most specific rule
+    /// that sets it. `rules` has to be ordered most specific first (as returned by `Layout::rules`).
+    /// <br>Returns None if no rule matches the window.
+    pub fn merge(rules: &[&WindowInfo], window: &WindowInfo) -> Option<WindowInfo> {
+        let mut matching_rules = rules.iter().filter(|rule| rule.matches(window));
+        let mut merged = (*matching_rules.next()?).clone();
+
+        for rule in matching_rules {
+            merged.screen_num = merged.screen_num.or(rule.screen_num);
+            // The screen size belongs to the position it was saved with.
+            if merged.pos.is_none() {
+                merged.pos = rule.pos.clone();
+                merged.screen_size = rule.screen_size;
+            }
+            merged.scale = merged.scale.or(rule.scale);
+            merged.margin = merged.margin.or(rule.margin);
+        }
+        Some(merged)
+    }
+
+    /// Returns the position to restore the window to, on the specified screen: the rule's position, scaled if it was
+    /// saved on a screen of a different size, or else the window's `current` position.
+    pub fn desired_pos(&self, screen: &ScreenInfo, current: &WindowPos) -> WindowPos {
+        match (&self.pos, self.screen_size) {
+            (Some(pos), Some(reference)) => pos.scaled(reference, screen, self.scale.unwrap_or_default()),
+            (Some(pos), None) => pos.clone(),
+            (None, _) => current.clone(),
+        }
+    }
+
+    /// Returns how specific this rule is: an explicit priority beats everything, then exact matches beat patterns,
+    /// and then a pattern with more literal characters beats one with fewer.
+    pub fn specificity(&self) -> Specificity {
+        let fields = [&self.owner_name, &self.name];
+        Specificity {
+            priority: self.priority.unwrap_or_default(),
+            exact: fields.iter().filter(|field| matches!(field, Exact(_))).count(),
+            literal: fields.iter().map(|field| field.literal_chars()).sum(),
+        }
+    }
+}
+
+// Characters that make a regex more than a literal string.
+const REGEX_SPECIAL: &[char] = &['\\', '.', '+', '*', '?', '(', ')', '|', '[', ']', '{', '}', '^', '$'];
+
+// Counts the regex's literal characters outside of any group: the ones that aren't special, or escaped, and aren't
+// made optional by a quantifier. Groups, classes and escapes like `\d` count for nothing.
+fn regex_literal_chars(pattern: &str) -> usize {
+    let mut chars = pattern.chars().peekable();
+    let (mut count, mut depth) = (0, 0usize);
+
+    while let Some(c) = chars.next() {
+        let literal = match c {
+            '\\' => chars.next().is_some_and(|escaped| !escaped.is_alphanumeric()),
+            '[' => {
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        ']' => break,
+                        _ => {}
+                    }
+                }
+                false
+            }
+            '{' => {
+                chars.by_ref().find(|c| *c == '}');
+                false
+            }
+            '(' => {
+                depth += 1;
+                false
+            }
+            ')' => {
+                depth = depth.saturating_sub(1);
+                false
+            }
+            c => !REGEX_SPECIAL.contains(&c),
+        };
+        let optional = matches!(chars.peek(), Some('*' | '?' | '{'));
+        if literal && depth == 0 && !optional {
+            count += 1;
+        }
     }
+    count
 }
 
+// Whether the regex has a `|` outside of any group (or character class), i.e. it's a choice between whole patterns.
+fn has_top_level_alternation(pattern: &str) -> bool {
+    let (mut depth, mut in_class, mut escaped) = (0usize, false, false);
+    for c in pattern.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ']' if in_class => in_class = false,
+            _ if in_class => {}
+            '[' => in_class = true,
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            '|' if depth == 0 => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// A window owner or name pattern. In the layout file, these are written as:
+/// - `!Exact text`: matches `text` exactly.
+/// - `!Regex pattern`: a regular expression, which has to match the whole value.
+/// - `!Glob pattern`: a glob pattern (`*`, `?` and `[...]`), which has to match the whole value.
+/// - plain `text`: the same as `!Exact` in a version 2 layout file. In a version 1 layout file it's the old behavior,
+///   i.e. an (unanchored) regular expression if it compiles as one, or an exact match if it doesn't.
+///
+/// In JSON and TOML, tagged patterns are written as single-entry maps instead, e.g. `{"Glob": "* - *"}`.
 #[derive(Debug, Clone)]
 pub enum MaybeRegex {
     Exact(String),
+    // A plain string in a version 1 layout file, that compiled as a regex. Unanchored.
     RE(Regex),
+    // The original pattern, and the anchored regex compiled from it.
+    Regex(String, Regex),
+    // The original glob pattern, and the anchored regex it was translated to.
+    Glob(String, Regex),
+    // A reference to one of the layout file's `aliases`, written as `$name`. Only exists until the layout file has
+    // been loaded (see `Layout::resolve_aliases`), and never matches anything.
+    Alias(String),
 }
 
 impl MaybeRegex {
     pub fn matches(&self, value: &str) -> bool {
         match self {
             Exact(s) => s.eq(value),
-            RE(r) => r.is_match(value),
+            RE(r) | MaybeRegex::Regex(_, r) | Glob(_, r) => r.is_match(value),
+            Alias(_) => false,
+        }
+    }
+
+    /// Returns the number of literal characters in the pattern, anywhere in it: the ones that every value it matches
+    /// has to contain, as opposed to wildcards. For an exact match, that's all of them.
+    pub fn literal_chars(&self) -> usize {
+        match self {
+            Exact(value) => value.chars().count(),
+            // An unanchored regex can match values with anything around it, so it doesn't count as specific at all.
+            Alias(_) | RE(_) => 0,
+            // With an alternation, no part of the pattern is required: `Foo|B` matches "B".
+            MaybeRegex::Regex(value, _) if has_top_level_alternation(value) => 0,
+            MaybeRegex::Regex(value, _) => regex_literal_chars(value),
+            Glob(value, _) => {
+                let mut chars = value.chars();
+                let mut count = 0;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '*' | '?' => {}
+                        '[' => {
+                            chars.by_ref().find(|c| *c == ']');
+                        }
+                        _ => count += 1,
+                    }
+                }
+                count
+            }
+        }
+    }
+
+    /// Compiles a `!Regex` pattern, anchored to the whole value.
+    pub fn regex(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(MaybeRegex::Regex(pattern.to_string(), Regex::new(&format!("^(?:{})$", pattern))?))
+    }
+
+    /// Translates a `!Glob` pattern into an anchored regex.
+    pub fn glob(pattern: &str) -> Result<Self, regex::Error> {
+        let mut regex = String::from("^");
+        let mut chars = pattern.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                '[' => {
+                    regex.push('[');
+                    if chars.next_if_eq(&'!').is_some() {
+                        regex.push('^');
+                    }
+                    for c in chars.by_ref() {
+                        if c == ']' {
+                            break;
+                        }
+                        if c == '\\' || c == '[' {
+                            regex.push('\\');
+                        }
+                        regex.push(c);
+                    }
+                    regex.push(']');
+                }
+                _ => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+
+        Ok(Glob(pattern.to_string(), Regex::new(&regex)?))
+    }
+
+    /// The version 2 meaning of a version 1 plain string, i.e. an exact match.
+    fn upgraded(&self) -> Self {
+        match self {
+            RE(r) => Exact(r.as_str().to_string()),
+            other => other.clone(),
+        }
+    }
+
+    /// The version 1 meaning of a `$name`, which was just a plain string (see `MaybeRegexVisitor::visit_str`).
+    fn downgraded(&self) -> Self {
+        match self {
+            Alias(name) => MaybeRegex::legacy(&format!("${}", name)),
+            other => other.clone(),
+        }
+    }
+
+    /// The version 1 meaning of a plain string: an (unanchored) regex if it compiles as one, and otherwise an exact
+    /// match.
+    fn legacy(text: &str) -> Self {
+        match Regex::new(text) {
+            Ok(value) => RE(value),
+            Err(_) => Exact(text.to_string()),
         }
     }
 }
@@ -174,21 +1204,36 @@ impl MaybeRegex {
 impl Display for MaybeRegex {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let str = match self {
-            Exact(value) => value.clone(),
-            RE(value) => value.as_str().to_string(),
+            Exact(value) => value.as_str(),
+            RE(value) => value.as_str(),
+            MaybeRegex::Regex(value, _) | Glob(value, _) => value.as_str(),
+            Alias(name) => return write!(f, "${}", name),
         };
         write!(f, "{}", str)
     }
 }
 
+thread_local! {
+    // Whether exact matches have to be written as `!Exact`, even when a plain string would do (see `Layout::to_text`).
+    static EXPLICIT_EXACT: Cell<bool> = const { Cell::new(false) };
+}
+
 impl serde::Serialize for MaybeRegex {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
         match self {
+            // A plain string would be a regex in a version 1 layout file, so be explicit when it would make a
+            // difference, and always when writing one.
+            Exact(value) if regex::escape(value) != *value || EXPLICIT_EXACT.with(Cell::get) => {
+                serializer.serialize_newtype_variant("MaybeRegex", 0, "Exact", value)
+            }
             Exact(value) => serializer.serialize_str(value),
             RE(value) => serializer.serialize_str(value.as_str()),
+            MaybeRegex::Regex(value, _) => serializer.serialize_newtype_variant("MaybeRegex", 1, "Regex", value),
+            Glob(value, _) => serializer.serialize_newtype_variant("MaybeRegex", 2, "Glob", value),
+            Alias(name) => serializer.serialize_str(&format!("${}", name)),
         }
     }
 }
@@ -198,13 +1243,91 @@ impl<'de> serde::Deserialize<'de> for MaybeRegex {
     where
         D: Deserializer<'de>,
     {
-        let text_val = String::deserialize(deserializer)?;
+        deserializer.deserialize_any(MaybeRegexVisitor)
+    }
+}
 
-        Ok(match Regex::new(&text_val) {
-            Ok(value) => RE(value),
-            Err(_) => Exact(text_val),
+struct MaybeRegexVisitor;
+
+impl<'de> Visitor<'de> for MaybeRegexVisitor {
+    type Value = MaybeRegex;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a string, or a string tagged with !Exact, !Regex or !Glob")
+    }
+
+    fn visit_str<E>(self, text_val: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        // `$name` is an alias, and anything else has the version 1 behavior. Either gets fixed up afterward, once
+        // the layout file's version is known (see `Layout::apply_version`).
+        Ok(match text_val.strip_prefix('$') {
+            Some(name) => Alias(name.to_string()),
+            None => MaybeRegex::legacy(text_val),
         })
     }
+
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        let (tag, value): (String, _) = data.variant()?;
+        let pattern: String = value.newtype_variant()?;
+
+        match tag.as_str() {
+            "Exact" | "exact" => Ok(Exact(pattern)),
+            "Regex" | "regex" => MaybeRegex::regex(&pattern).map_err(serde::de::Error::custom),
+            "Glob" | "glob" => MaybeRegex::glob(&pattern).map_err(serde::de::Error::custom),
+            _ => Err(serde::de::Error::unknown_variant(&tag, &["Exact", "Regex", "Glob"])),
+        }
+    }
+
+    // E.g. `{glob: "* - *"}`, for formats without tags.
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        self.visit_enum(MapAccessDeserializer::new(map))
+    }
+}
+
+// Written as a plain string, or as a single-entry map of its kind to the pattern (which is what YAML tags amount to).
+impl JsonSchema for MaybeRegex {
+    fn schema_name() -> Cow<'static, str> {
+        "Pattern".into()
+    }
+
+    fn json_schema(_generator: &mut SchemaGenerator) -> Schema {
+        let tagged = |tag: &str, description: &str| {
+            json_schema!({
+                "type": "object",
+                "properties": { tag: { "type": "string", "description": description } },
+                "required": [tag],
+                "additionalProperties": false
+            })
+        };
+
+        let mut schema = json_schema!({
+            "description": "A window owner or name pattern: plain text (an exact match in a version 2 layout file), \
+                            !Exact text, !Regex pattern or !Glob pattern, or a $name from the aliases section.",
+            "anyOf": [
+                { "type": "string" },
+                tagged("Exact", "Matches the text exactly."),
+                tagged("Regex", "A regular expression, which has to match the whole value."),
+                tagged("Glob", "A glob pattern (*, ? and [...]), which has to match the whole value.")
+            ]
+        });
+        with_snake_case_names(&mut schema);
+        schema
+    }
+}
+
+// Two patterns are the same if they're the same kind, and written the same.
+impl PartialEq for MaybeRegex {
+    fn eq(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other) && self.to_string() == other.to_string()
+    }
 }
 
 impl Default for MaybeRegex {
@@ -228,18 +1351,36 @@ impl Rect {
             && (self.h - other.h).abs() < 4
     }
 
-    pub fn origin(&self) -> CGPoint {
-        CGPoint {
-            x: self.x as CGFloat,
-            y: self.y as CGFloat,
-        }
+    /// Returns the rect shrunk by `amount` on every side. However big `amount` is, at least a pixel is left (see
+    /// `Rect::shrink`).
+    pub fn inset(&self, amount: i32) -> Rect {
+        self.shrink(amount, amount, amount, amount)
     }
 
-    pub fn size(&self) -> CGSize {
-        CGSize {
-            width: self.w as CGFloat,
-            height: self.h as CGFloat,
-        }
+    // Shrinks the edges that aren't on the edge of `area`, so that two neighbors end up `gap` apart: the left and top
+    // edges take the larger half of an odd gap.
+    fn inset_inner_edges(&self, area: &Rect, gap: i32) -> Rect {
+        let left = if self.x > area.x { gap - gap / 2 } else { 0 };
+        let top = if self.y > area.y { gap - gap / 2 } else { 0 };
+        let right = if self.x + self.w < area.x + area.w { gap / 2 } else { 0 };
+        let bottom = if self.y + self.h < area.y + area.h { gap / 2 } else { 0 };
+        self.shrink(left, top, right, bottom)
+    }
+
+    // Moves each edge inward by the specified amount. When the edges would meet (or cross), the rect is shrunk to a
+    // single pixel where they meet instead, so that it never ends up with no size, or outside of itself.
+    fn shrink(&self, left: i32, top: i32, right: i32, bottom: i32) -> Rect {
+        let span = |start: i32, length: i32, before: i32, after: i32| {
+            let room = (length - 1).max(0);
+            if before + after <= room {
+                return (start + before, length - before - after);
+            }
+            let before = (room as i64 * before.max(0) as i64 / (before.max(0) + after.max(0)).max(1) as i64) as i32;
+            (start + before, 1)
+        };
+        let (x, w) = span(self.x, self.w, left, right);
+        let (y, h) = span(self.y, self.h, top, bottom);
+        Rect { x, y, w, h }
     }
 }
 
@@ -270,41 +1411,537 @@ impl<'de> serde::Deserialize<'de> for Rect {
         D: Deserializer<'de>,
     {
         let text_val = String::deserialize(deserializer)?;
-        let coords: Vec<_> = text_val.split(",").collect();
-        Ok(Rect {
-            x: coords[0].parse().unwrap(),
-            y: coords[1].parse().unwrap(),
-            w: coords[2].parse().unwrap(),
-            h: coords[3].parse().unwrap(),
+        let invalid = || serde::de::Error::custom(format!("Invalid rect '{}', expected x,y,w,h", text_val));
+
+        let coords = text_val
+            .split(',')
+            .map(|coord| coord.trim().parse().map_err(|_| invalid()))
+            .collect::<Result<Vec<i32>, _>>()?;
+        match coords[..] {
+            [x, y, w, h] => Ok(Rect { x, y, w, h }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl JsonSchema for Rect {
+    fn schema_name() -> Cow<'static, str> {
+        "Rect".into()
+    }
+
+    fn json_schema(_generator: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "x,y,w,h, e.g. 0,25,1646,1055",
+            "type": "string",
+            "pattern": "^\\s*-?\\d+\\s*(,\\s*-?\\d+\\s*){3}$"
         })
     }
 }
 
-impl Into<CGRect> for Rect {
-    fn into(self) -> CGRect {
-        CGRect {
-            origin: CGPoint {
-                x: self.x.into(),
-                y: self.y.into(),
-            },
-            size: CGSize {
-                width: self.w.into(),
-                height: self.h.into(),
-            },
+// Written like a `Rect`, e.g. "0.25,0,0.5,1".
+impl serde::Serialize for FracRect {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&format!("{},{},{},{}", self.x, self.y, self.w, self.h))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for FracRect {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text_val = String::deserialize(deserializer)?;
+        let invalid = || {
+            serde::de::Error::custom(format!(
+                "Invalid fractional rect '{}', expected x,y,w,h as fractions of the screen, e.g. 0.25,0,0.5,1",
+                text_val
+            ))
+        };
+
+        let fractions = text_val
+            .split(',')
+            .map(|fraction| fraction.trim().parse().map_err(|_| invalid()))
+            .collect::<Result<Vec<f32>, _>>()?;
+        match fractions[..] {
+            [x, y, w, h] => Ok(FracRect { x, y, w, h }),
+            _ => Err(invalid()),
         }
     }
 }
 
-impl From<CGRect> for Rect {
-    fn from(value: CGRect) -> Self {
-        Rect {
-            x: value.origin.x as i32,
-            y: value.origin.y as i32,
-            w: value.size.width as i32,
-            h: value.size.height as i32,
+impl JsonSchema for FracRect {
+    fn schema_name() -> Cow<'static, str> {
+        "FracRect".into()
+    }
+
+    fn json_schema(_generator: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "x,y,w,h as fractions of the screen's width and height, e.g. 0.25,0,0.5,1",
+            "type": "string",
+            "pattern": "^\\s*\\d*\\.?\\d+\\s*(,\\s*\\d*\\.?\\d+\\s*){3}$"
+        })
+    }
+}
+
+// Written like a `Rect`, leaving out an offset of 0,0, e.g. "400,143" or "0.5,0.5,0,40".
+impl serde::Serialize for Anchored {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let output = match (self.dx, self.dy) {
+            (Length::Pixels(0), Length::Pixels(0)) => format!("{},{}", self.w, self.h),
+            _ => format!("{},{},{},{}", self.w, self.h, self.dx, self.dy),
+        };
+        serializer.serialize_str(&output)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Anchored {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text_val = String::deserialize(deserializer)?;
+        let lengths = text_val
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<Vec<Length>, _>>()
+            .map_err(serde::de::Error::custom)?;
+
+        match lengths[..] {
+            [w, h] => Ok(Anchored {
+                w,
+                h,
+                ..Anchored::default()
+            }),
+            [w, h, dx, dy] => Ok(Anchored { w, h, dx, dy }),
+            _ => Err(serde::de::Error::custom(format!("Invalid size '{}', expected w,h or w,h,dx,dy", text_val))),
         }
     }
 }
 
+impl JsonSchema for Anchored {
+    fn schema_name() -> Cow<'static, str> {
+        "Anchored".into()
+    }
+
+    fn json_schema(_generator: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "w,h or w,h,dx,dy, in pixels or (with a decimal point) as fractions of the screen, e.g. \
+                            400,143,50,57 or 0.5,0.5",
+            "type": "string",
+            "pattern": "^\\s*-?\\d*\\.?\\d+\\s*(,\\s*-?\\d*\\.?\\d+\\s*){1}((,\\s*-?\\d*\\.?\\d+\\s*){2})?$"
+        })
+    }
+}
+
+impl From<&Rect> for Size {
+    fn from(value: &Rect) -> Self {
+        Size { w: value.w, h: value.h }
+    }
+}
+
+// Sizes are written the usual way, e.g. "2560x1440".
+impl serde::Serialize for Size {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&format!("{}x{}", self.w, self.h))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Size {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text_val = String::deserialize(deserializer)?;
+        let (w, h) = text_val
+            .split_once('x')
+            .ok_or_else(|| serde::de::Error::custom(format!("Invalid size '{}', expected e.g. 2560x1440", text_val)))?;
+
+        Ok(Size {
+            w: w.trim().parse().map_err(serde::de::Error::custom)?,
+            h: h.trim().parse().map_err(serde::de::Error::custom)?,
+        })
+    }
+}
+
+impl JsonSchema for Size {
+    fn schema_name() -> Cow<'static, str> {
+        "Size".into()
+    }
+
+    fn json_schema(_generator: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "wxh, e.g. 2560x1440",
+            "type": "string",
+            "pattern": "^\\s*\\d+\\s*x\\s*\\d+\\s*$"
+        })
+    }
+}
+
 pub const MIN_WIDTH: i32 = 64;
 pub const MIN_HEIGHT: i32 = 64;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(yaml: &str) -> MaybeRegex {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn window_pos(yaml: &str) -> WindowPos {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn layout(yaml: &str) -> Layout {
+        let mut layout: Layout = serde_yaml::from_str(yaml).unwrap();
+        layout.apply_version();
+        layout
+    }
+
+    #[test]
+    fn tagged_patterns_match_the_whole_value() {
+        assert!(pattern("!Exact Inbox (3)").matches("Inbox (3)"));
+        assert!(!pattern("!Exact Inbox (3)").matches("Inbox 3"));
+
+        assert!(pattern("!Regex Build").matches("Build"));
+        assert!(!pattern("!Regex Build").matches("Build Tools"));
+        assert!(pattern("!Regex Build|Run").matches("Run"));
+
+        assert!(pattern("!Glob Build*").matches("Build Tools"));
+        assert!(!pattern("!Glob Build*").matches("My Build"));
+        assert!(pattern("!Glob Inbox (?)").matches("Inbox (3)"));
+        assert!(pattern("!Glob \"[!A-Z]*.rs\"").matches("main.rs"));
+        assert!(!pattern("!Glob \"[!A-Z]*.rs\"").matches("Main.rs"));
+    }
+
+    fn rule(owner_name: MaybeRegex, name: MaybeRegex) -> WindowInfo {
+        WindowInfo {
+            owner_name,
+            name,
+            ..WindowInfo::default()
+        }
+    }
+
+    fn window(owner_name: &str, name: &str) -> WindowInfo {
+        rule(Exact(owner_name.into()), Exact(name.into()))
+    }
+
+    #[test]
+    fn rules_match_windows_but_not_the_other_way_around() {
+        let terminal = rule(Exact("Terminal".into()), Exact("bash".into()));
+
+        // These windows' titles would match the rule, if they were treated as patterns.
+        assert!(!terminal.matches(&window("Terminal", ".*")));
+        assert!(!terminal.matches(&window("Terminal", "bash|zsh")));
+        assert!(!terminal.matches(&window(".*", "bash")));
+        assert!(terminal.matches(&window("Terminal", "bash")));
+
+        let any_title = rule(Exact("Terminal".into()), pattern("!Regex .*"));
+        assert!(any_title.matches(&window("Terminal", "bash|zsh")));
+        assert!(!any_title.matches(&window("iTerm2", "bash")));
+    }
+
+    #[test]
+    fn titles_with_regex_metacharacters_match_literally() {
+        for title in [
+            "Inbox (3)",
+            "a|b",
+            "[WIP] main.rs",
+            "$5.00 + tax?",
+            "C:\\Users\\*",
+            "^.*$",
+        ] {
+            let exact = rule(Exact("App".into()), Exact(title.into()));
+            assert!(exact.matches(&window("App", title)), "{}", title);
+            assert!(!exact.matches(&window("App", &format!("{} ", title))), "{}", title);
+
+            let escaped = rule(Exact("App".into()), MaybeRegex::regex(&regex::escape(title)).unwrap());
+            assert!(escaped.matches(&window("App", title)), "{}", title);
+            assert!(!escaped.matches(&window("App", "x")), "{}", title);
+        }
+
+        // In a version 1 file, "Inbox (3)" is a regex that matches "Inbox 3", but not itself.
+        let legacy = rule(Exact("Microsoft Outlook".into()), pattern("Inbox (3)"));
+        assert!(legacy.matches(&window("Microsoft Outlook", "Inbox 3")));
+        assert!(!legacy.matches(&window("Microsoft Outlook", "Inbox (3)")));
+    }
+
+    #[test]
+    fn literal_chars() {
+        assert_eq!(Exact("Inbox (3)".into()).literal_chars(), 9);
+        assert_eq!(pattern("!Regex Inbox.*").literal_chars(), 5);
+        assert_eq!(pattern("!Regex Inboxes?").literal_chars(), 6);
+        assert_eq!(pattern(r"!Regex \d+ - Build( Tools)?").literal_chars(), 8);
+        assert_eq!(pattern("!Regex (Inbox)|(Outbox)").literal_chars(), 0);
+        assert_eq!(pattern("!Regex Foo|B").literal_chars(), 0);
+        assert_eq!(pattern("!Regex Inbox (Unread|Flagged)").literal_chars(), 6);
+        assert_eq!(pattern(r"!Regex Inbox[|]\|.{2,3}").literal_chars(), 6);
+        // A version 1 regex isn't anchored, so it can match anything with "Inbox" in it.
+        assert!(matches!(pattern("Inbox.*"), RE(_)));
+        assert_eq!(pattern("Inbox.*").literal_chars(), 0);
+        assert_eq!(pattern("!Glob Inbox (?)").literal_chars(), 8);
+        assert_eq!(pattern("!Glob '* - SmartGit*'").literal_chars(), 11);
+        assert_eq!(pattern("!Glob '[Ii]nbox*'").literal_chars(), 4);
+        assert_eq!(pattern("!Glob \"*\"").literal_chars(), 0);
+    }
+
+    #[test]
+    fn exact_matches_are_more_specific_than_patterns() {
+        let exact = rule(Exact("App".into()), Exact("Build".into()));
+        let long_glob = rule(Exact("App".into()), pattern("!Glob Build Tools*"));
+        let short_glob = rule(Exact("App".into()), pattern("!Glob Build*"));
+        let prioritized = WindowInfo {
+            priority: Some(1),
+            ..rule(pattern("!Regex .*"), pattern("!Regex .*"))
+        };
+
+        assert!(exact.specificity() > long_glob.specificity());
+        assert!(long_glob.specificity() > short_glob.specificity());
+        // "Foo|B" only requires "B" (or "Foo"), so it doesn't outrank a pattern that requires "Build".
+        let alternation = rule(Exact("App".into()), pattern("!Regex Foo|B"));
+        assert!(short_glob.specificity() > alternation.specificity());
+        // Literal characters count wherever they are, not just at the start.
+        let main_window = rule(Exact("SmartGit".into()), pattern("!Glob '* - SmartGit*'"));
+        let log = rule(Exact("SmartGit".into()), pattern("!Glob '* - Log*'"));
+        let everything = rule(Exact("SmartGit".into()), pattern("!Glob '*'"));
+        assert!(main_window.specificity() > log.specificity());
+        assert!(log.specificity() > everything.specificity());
+        assert!(prioritized.specificity() > exact.specificity());
+    }
+
+    #[test]
+    fn aliases_are_resolved() {
+        let yaml = r#"
+            version: 2
+            aliases:
+              jetbrains: !Regex (RustRover)|(CLion)
+            positions:
+              ide_main: !Left 0.75
+            windows:
+              - owner_name: $jetbrains
+                name: !Exact $5 off
+                pos: $ide_main
+            profiles:
+              laptop:
+                windows:
+                  - owner_name: Terminal
+                    name: $jetbrains
+        "#;
+        let mut layout = layout(yaml);
+
+        // Until they're resolved, aliases are saved as they were written.
+        let saved = serde_yaml::to_string(&layout).unwrap();
+        assert!(saved.contains("owner_name: $jetbrains"), "{}", saved);
+        assert!(saved.contains("pos: $ide_main"), "{}", saved);
+
+        layout.resolve_aliases().unwrap();
+        let rule = &layout.windows[0];
+        assert!(rule.matches(&window("CLion", "$5 off")));
+        assert!(matches!(rule.pos, Some(WindowPos::Left(fraction)) if fraction == 0.75));
+        assert!(layout.profiles["laptop"].windows[0].matches(&window("Terminal", "RustRover")));
+    }
+
+    #[test]
+    fn positions_can_be_written_as_maps() {
+        let mut layout = layout(
+            "version: 2\npositions:\n  half: {left: 0.5}\nwindows:\n  - owner_name: Slack\n    name: Slack\n    \
+             pos: $half\n  - owner_name: Music\n    name: Music\n    pos: {Pos: '0,0,400,300'}\n",
+        );
+        layout.resolve_aliases().unwrap();
+
+        assert!(matches!(layout.windows[0].pos, Some(WindowPos::Left(fraction)) if fraction == 0.5));
+        assert!(matches!(&layout.windows[1].pos, Some(WindowPos::Pos(rect)) if rect.w == 400));
+        assert!(serde_yaml::from_str::<Layout>("positions:\n  half: $other\n").is_err());
+    }
+
+    #[test]
+    fn null_positions_are_left_out() {
+        let yaml = layout("version: 2\nwindows:\n  - owner_name: Slack\n    name: Slack\n    pos: null\n");
+        let json: Layout = serde_json::from_str(
+            r#"{"version": 2, "windows": [{"owner_name": "Slack", "name": "Slack", "pos": null}]}"#,
+        )
+        .unwrap();
+
+        assert!(yaml.windows[0].pos.is_none());
+        assert!(json.windows[0].pos.is_none());
+        // `!Maxed` has a null value too, but it's tagged.
+        let maxed = layout("version: 2\nwindows:\n  - owner_name: Slack\n    name: Slack\n    pos: !Maxed\n");
+        assert!(matches!(maxed.windows[0].pos, Some(WindowPos::Maxed)));
+    }
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rect {
+        Rect { x, y, w, h }
+    }
+
+    #[test]
+    fn grid_cells_tile_the_screen() {
+        let screen = ScreenInfo {
+            frame: rect(0, 25, 3440, 1415),
+            screen_id: 1,
+        };
+        let cell = |yaml: &str| window_pos(yaml).to_absolute(&screen, Spacing::default());
+
+        assert_eq!(cell("!Grid {columns: 3, rows: 2, column: 1, row: 1}"), rect(0, 25, 1146, 707));
+        assert_eq!(cell("!Grid {columns: 3, rows: 2, column: 2, row: 1}"), rect(1146, 25, 1147, 707));
+        assert_eq!(cell("!Grid {columns: 3, rows: 2, column: 1, row: 2, column_span: 3}"), rect(0, 732, 3440, 708));
+
+        // Nonsense is clamped rather than crashing.
+        assert_eq!(cell("!Grid {columns: 0, rows: 2, column: 5, row: 0}"), rect(0, 25, 3440, 707));
+    }
+
+    #[test]
+    fn pos_scales_from_the_saved_screen_size() {
+        let screen = ScreenInfo {
+            frame: rect(0, 0, 3840, 2160),
+            screen_id: 1,
+        };
+        let reference = Size { w: 1920, h: 1080 };
+        let reminder = WindowPos::Pos(rect(-450, -200, 400, 143));
+
+        let scaled = |scale| {
+            reminder
+                .scaled(reference, &screen, scale)
+                .to_absolute(&screen, Spacing::default())
+        };
+        assert_eq!(scaled(Scale::Proportional), rect(2940, 1760, 800, 286));
+        assert_eq!(scaled(Scale::KeepSize), rect(3140, 1831, 400, 143));
+        assert_eq!(scaled(Scale::None), rect(3390, 1960, 400, 143));
+
+        // Nothing to do on a screen of the same size, or for positions that aren't in pixels.
+        assert!(
+            matches!(reminder.scaled(Size { w: 3840, h: 2160 }, &screen, Scale::Proportional), WindowPos::Pos(r) if r.w == 400)
+        );
+        assert!(matches!(WindowPos::Left(0.5).scaled(reference, &screen, Scale::Proportional), WindowPos::Left(_)));
+    }
+
+    #[test]
+    fn anchored_positions() {
+        let screen = ScreenInfo {
+            frame: rect(0, 25, 2560, 1415),
+            screen_id: 1,
+        };
+        let anchored = |yaml: &str| window_pos(yaml).to_absolute(&screen, Spacing::default());
+
+        assert_eq!(anchored("!Center 800,600"), rect(880, 432, 800, 600));
+        assert_eq!(anchored("!Center 0.5,0.5,0,-100"), rect(640, 278, 1280, 708));
+        assert_eq!(anchored("!TopLeft 400,300,10,10"), rect(10, 35, 400, 300));
+        assert_eq!(anchored("!TopRight 0.25,1.0"), rect(1920, 25, 640, 1415));
+        assert_eq!(anchored("!BottomLeft 400,300"), rect(0, 1140, 400, 300));
+        assert_eq!(anchored("!BottomRight 400,143,50,57"), rect(2110, 1240, 400, 143));
+
+        // Fractions keep their decimal point, so they stay fractions.
+        let saved = serde_yaml::to_string(&window_pos("!TopRight 1.0,400")).unwrap();
+        assert_eq!(saved, "!TopRight 1.0,400\n");
+        assert!(serde_yaml::from_str::<WindowPos>("!Center 400").is_err());
+    }
+
+    #[test]
+    fn gaps_and_margins() {
+        let screen = ScreenInfo {
+            frame: rect(0, 25, 2560, 1415),
+            screen_id: 1,
+        };
+        let gaps = Gaps { outer: 10, inner: 20 };
+        let spaced = |yaml: &str, margin| window_pos(yaml).to_absolute(&screen, Spacing { gaps, margin });
+
+        // The outer gap is taken off the screen, and neighbors are the inner gap apart.
+        assert_eq!(spaced("!Maxed", 0), rect(10, 35, 2540, 1395));
+        assert_eq!(spaced("!Left 0.5", 0), rect(10, 35, 1260, 1395));
+        assert_eq!(spaced("!Right 0.5", 0), rect(1290, 35, 1260, 1395));
+        assert_eq!(spaced("!Grid {columns: 3, rows: 2, column: 2, row: 2}", 0), rect(866, 742, 827, 688));
+        assert_eq!(spaced("!Center 800,600", 5), rect(885, 437, 790, 590));
+        // An exact position is left alone.
+        assert_eq!(spaced("!Pos 0,0,800,600", 5), rect(0, 25, 800, 600));
+
+        // Gaps and margins that are too big for the screen leave a pixel, rather than a negative size.
+        let huge = Spacing {
+            gaps: Gaps { outer: 2000, inner: 20 },
+            margin: 0,
+        };
+        assert_eq!(window_pos("!Maxed").to_absolute(&screen, huge), rect(1279, 732, 1, 1));
+        let cell = window_pos("!Grid {columns: 4, rows: 1, column: 2, row: 1}")
+            .to_absolute(&screen, Spacing { gaps, margin: 400 });
+        assert!(cell.w == 1 && cell.x >= 640 && cell.x < 1280, "{:?}", cell);
+
+        // An odd gap is split as evenly as it can be.
+        let odd = Spacing {
+            gaps: Gaps { outer: 0, inner: 5 },
+            margin: 0,
+        };
+        let left = window_pos("!Left 0.5").to_absolute(&screen, odd);
+        let right = window_pos("!Right 0.5").to_absolute(&screen, odd);
+        assert_eq!(right.x - (left.x + left.w), 5);
+    }
+
+    #[test]
+    fn undefined_aliases_are_an_error() {
+        let mut owner = layout("version: 2\nwindows:\n  - owner_name: $jetbrains\n    name: Find\n");
+        let error = owner.resolve_aliases().unwrap_err().to_string();
+        assert!(error.contains("'$jetbrains' is not defined in the aliases section"), "{}", error);
+
+        let mut pos = layout("windows:\n  - owner_name: RustRover\n    name: Find\n    pos: $floating\n");
+        let error = pos.resolve_aliases().unwrap_err().to_string();
+        assert!(error.contains("'$floating' is not defined in the positions section"), "{}", error);
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        assert!(serde_yaml::from_str::<MaybeRegex>("!Regex (unclosed").is_err());
+    }
+
+    #[test]
+    fn plain_strings_depend_on_the_version() {
+        let rules = "windows:\n  - owner_name: Xcode\n    name: Build\n    screen_num: 1\n    pos: !Maxed\n";
+
+        // Version 1: an unanchored regex.
+        let v1 = layout(rules);
+        assert_eq!(v1.version, 1);
+        assert!(v1.windows[0].name.matches("Build Tools"));
+
+        let v2 = layout(&format!("version: 2\n{}", rules));
+        assert!(v2.windows[0].name.matches("Build"));
+        assert!(!v2.windows[0].name.matches("Build Tools"));
+    }
+
+    #[test]
+    fn dollar_strings_are_only_aliases_from_version_2() {
+        let rules = "windows:\n  - owner_name: Safari\n    name: \"$5 off\"\n    pos: $ide_main\n";
+
+        // Version 1 had no aliases, so the title is the same regex (or exact match) it always was. Positions could
+        // never be plain strings, so `$name` still refers to a named position.
+        let mut v1 = layout(&format!("positions:\n  ide_main: !Maxed\n{}", rules));
+        v1.resolve_aliases().unwrap();
+        assert!(matches!(&v1.windows[0].name, RE(regex) if regex.as_str() == "$5 off"));
+        assert!(matches!(v1.windows[0].pos, Some(WindowPos::Maxed)));
+        let mut v1_exact = layout("windows:\n  - owner_name: Shop\n    name: \"$[sale\"\n");
+        v1_exact.resolve_aliases().unwrap();
+        assert!(v1_exact.windows[0].name.matches("$[sale"));
+
+        let mut v2 = layout(&format!("version: 2\npositions:\n  ide_main: !Maxed\n{}", rules));
+        let error = v2.resolve_aliases().unwrap_err().to_string();
+        assert!(error.contains("'$5 off' is not defined in the aliases section"), "{}", error);
+    }
+
+    #[test]
+    fn patterns_round_trip() {
+        for yaml in [
+            "Terminal",
+            "!Exact Inbox (3)",
+            "!Regex .* - .*",
+            "!Glob '* - SmartGit*'",
+        ] {
+            let saved = serde_yaml::to_string(&pattern(yaml)).unwrap();
+            assert_eq!(serde_yaml::to_string(&pattern(&saved)).unwrap(), saved);
+        }
+        assert_eq!(serde_yaml::to_string(&Exact("Inbox (3)".into())).unwrap(), "!Exact Inbox (3)\n");
+    }
+}
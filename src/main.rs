@@ -1,51 +1,39 @@
+#[cfg(target_os = "macos")]
 #[macro_use]
 extern crate objc;
 
-use std::collections::{BTreeMap, HashSet};
-use std::ffi::c_void;
-use std::fs::File;
-use std::io::BufReader;
-use std::thread::sleep;
-use std::time::Duration;
-
-use accessibility_sys::{
-    kAXErrorSuccess, kAXPositionAttribute, kAXSizeAttribute, kAXValueTypeCGPoint, kAXValueTypeCGSize,
-    kAXWindowsAttribute, AXError, AXUIElementCopyAttributeValue, AXUIElementCreateApplication, AXUIElementRef,
-    AXUIElementSetAttributeValue, AXValueCreate,
-};
-use anyhow::anyhow;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
 use args::Args;
 use clap::Parser;
-use cocoa::appkit::NSScreen;
-use cocoa_foundation::base::{id, nil};
-use cocoa_foundation::foundation::{NSArray, NSFastEnumeration, NSString, NSUInteger};
-use core_foundation::base::*;
-use core_foundation::dictionary::CFDictionary;
-use core_foundation::string::*;
-use core_foundation_sys::dictionary::CFDictionaryRef;
-use core_graphics::display;
-use core_graphics::display::{CGDisplay, CGWindowID};
-use core_graphics_types::geometry::{CGPoint, CGRect, CGSize};
-use layout_types::{Layout, Rect, ScreenInfo, WindowInfo, MIN_HEIGHT, MIN_WIDTH};
-use log::{debug, error, trace, LevelFilter};
+use layout_types::{Layout, Profile, ScreenInfo, ScreenSignature, Size, WindowInfo, MIN_HEIGHT, MIN_WIDTH};
+use log::{debug, error, info, trace, LevelFilter};
 use log4rs::append::console::{ConsoleAppender, Target};
 use log4rs::config::{Appender, Root};
 use regex::Regex;
 
-use crate::args::Command;
-use crate::dict_utils::{get_dict_from_dict, get_num_from_dict, get_string_from_dict};
-use crate::idref::IdRef;
+use crate::args::{Command, ConfigCommand, ReportFormat};
+use crate::config::LayoutFile;
+use crate::error::LayoutError;
+use crate::format::LayoutFormat;
 use crate::layout_types::MaybeRegex::Exact;
-use crate::layout_types::{MatchingWindowInfo, WindowPos};
-
-extern "C" {
-    pub fn _AXUIElementGetWindow(element: AXUIElementRef, out: *mut CGWindowID) -> AXError;
-}
+use crate::layout_types::{MatchingWindowInfo, Spacing, WindowPos};
+use crate::validate::Problem;
+use crate::window_system::recording::{Recording, RecordingWindowSystem, ReplayWindowSystem};
+use crate::window_system::WindowSystem;
 
 mod args;
+mod config;
+#[cfg(target_os = "macos")]
 mod dict_utils;
+mod error;
+mod format;
+#[cfg(target_os = "macos")]
 mod idref;
 mod layout_types;
+mod validate;
+mod window_system;
 
 /// See args.rs for command line arguments.
 fn main() {
@@ -53,10 +41,48 @@ fn main() {
 
     initialize_logging(args.log_level);
 
-    match args.command() {
-        Command::Restore => restore_layout(args.path),
-        Command::Save => save_layout(),
+    let result = match args.command() {
+        Command::Restore => create_window_system(&args).and_then(|window_system| {
+            let desired_layout = load_layout_files(&layout_files(&args)?)?;
+            restore_layout(window_system.as_ref(), &desired_layout, args.profile.as_deref(), args.explain)
+        }),
+        Command::Save { format, relative } => create_window_system(&args).and_then(|window_system| {
+            let options = SaveOptions { format, relative };
+            save_layout(window_system.as_ref(), args.path().to_string(), args.profile.clone(), options)
+        }),
+        Command::Validate { format } => layout_files(&args).and_then(|files| validate_layout_files(&files, format)),
+        Command::Schema => print_schema(),
+        Command::Config {
+            command: ConfigCommand::Paths,
+        } => layout_files(&args).and_then(|files| print_config_paths(&files)),
+    };
+
+    if let Err(e) = result {
+        error!("{}", e);
+        std::process::exit(error::exit_code(&e));
+    }
+}
+
+/// Returns every place a layout file is looked for. The user's own file has to exist if it was specified explicitly
+/// (with --path or LAYOUT_FILE).
+fn layout_files(args: &Args) -> anyhow::Result<Vec<LayoutFile>> {
+    let path = expand_path(args.path())?;
+
+    Ok(config::layout_files(Path::new(&path), args.path_is_explicit()))
+}
+
+/// Returns the window system to use: normally the native one, but possibly a recording or replay.
+fn create_window_system(args: &Args) -> anyhow::Result<Box<dyn WindowSystem>> {
+    if let Some(path) = &args.replay {
+        return Ok(Box::new(ReplayWindowSystem::new(Recording::load(path)?)));
     }
+
+    let window_system = window_system::native_window_system()?;
+
+    Ok(match &args.record {
+        Some(path) => Box::new(RecordingWindowSystem::new(window_system, path.clone())),
+        None => window_system,
+    })
 }
 
 /// Initialize the logging. Logging goes to stderr, so as not to interfere with the layout output when
@@ -71,38 +97,123 @@ fn initialize_logging(log_level: LevelFilter) {
     log4rs::config::init_config(log_config.unwrap()).unwrap();
 }
 
-/// Enumerate the current screens and windows, and dump to stdout.
-fn save_layout() {
-    let screens = get_screens();
-    let layout = get_current_layout(&screens);
+/// How `layout save` writes the current layout.
+#[derive(Debug, Clone, Copy, Default)]
+struct SaveOptions {
+    // The format to print the layout in. (A profile is saved in the layout file's own format.)
+    format: LayoutFormat,
+    // Whether to save positions as fractions of the screen, rather than in pixels.
+    relative: bool,
+}
+
+/// Enumerate the current screens and windows, and dump to stdout in the specified format. Or, if a profile is
+/// specified, save them to that profile in the layout file (in the layout file's own format).
+fn save_layout(
+    window_system: &dyn WindowSystem,
+    path: String,
+    profile: Option<String>,
+    options: SaveOptions,
+) -> anyhow::Result<()> {
+    let screens = get_screens(window_system)?;
+    let mut layout = get_current_layout(window_system, &screens)?;
+
+    if options.relative {
+        for window in &mut layout.windows {
+            if let (Some(screen_num), Some(pos)) = (window.screen_num, &window.pos) {
+                window.pos = Some(pos.to_fraction(&screens[screen_num - 1]));
+                // A fraction fits any screen.
+                window.screen_size = None;
+            }
+        }
+    }
 
-    println!("{}", serde_yaml::to_string(&layout).unwrap());
+    match profile {
+        Some(profile) => save_profile(path, profile, &screens, layout.windows),
+        None => {
+            println!("{}", layout.to_text(options.format)?);
+            Ok(())
+        }
+    }
 }
 
-/// Loads the desired layout, and moves all matching windows to their desired position.
-fn restore_layout(path: String) {
-    let desired_layout = load_layout_file(path);
+/// Adds (or replaces) the specified profile in the layout file, leaving everything else in it as is.
+/// <br>A new profile gets the signature of the current screens, so that it will be chosen automatically the next
+/// time these screens are connected. An existing profile keeps its signature, in case it has been hand-edited.
+fn save_profile(path: String, profile: String, screens: &[ScreenInfo], windows: Vec<WindowInfo>) -> anyhow::Result<()> {
+    let path = expand_path(&path)?;
+
+    // The file is read as is, so that aliases are written back out as aliases.
+    let mut layout = match Path::new(&path).exists() {
+        true => read_layout_file(&path)?,
+        false => Layout::default(),
+    };
+    let signature = match layout.profiles.get(&profile) {
+        Some(existing) => existing.screens.clone(),
+        None => Some(ScreenSignature::of(screens)),
+    };
+    layout.profiles.insert(
+        profile.clone(),
+        Profile {
+            screens: signature,
+            windows,
+        },
+    );
+
+    let text = layout.to_text(LayoutFormat::of(Path::new(&path)))?;
+    std::fs::write(&path, text).map_err(|source| LayoutError::write(&path, source))?;
+
+    info!("Saved profile '{}' to {}", profile, path);
+    Ok(())
+}
 
+/// Moves all windows that match the desired layout (or the specified profile in it) to their desired position.
+fn restore_layout(
+    window_system: &dyn WindowSystem,
+    desired_layout: &Layout,
+    profile: Option<&str>,
+    explain: bool,
+) -> anyhow::Result<()> {
     // I have noticed that when moving from a small monitor to a large (e.g. 4K) one, the window gets
     // moved but does not get resized properly. So rather than introduce complex logic I'm just going to
     // try looping through all windows twice.
-    let screens = get_screens();
+    let screens = get_screens(window_system)?;
+
+    // If no profile was specified, pick the one that matches the current screens.
+    let profile = match profile {
+        Some(profile) => Some(profile),
+        None => {
+            let profile = desired_layout.select_profile(&screens);
+            if let Some(profile) = profile {
+                info!("Using profile '{}' for the current screens", profile);
+            }
+            profile
+        }
+    };
+    let rules = desired_layout.rules(profile)?;
 
-    for _ in 0..2 {
-        let current_layout = get_current_layout(&screens);
+    for pass in 0..2 {
+        let current_layout = get_current_layout(window_system, &screens)?;
 
         for window_info in current_layout.windows {
-            // See if there's a match for the Owner + Window names in the desired layout.
+            if explain && pass == 0 {
+                explain_match(&window_info, &rules);
+            }
+
+            // Merge every rule that matches the Owner + Window names in the desired layout.
             //
-            // Note: Vec::find() is O(n) and thus the entire loop is basically O(n^2) but whatevs.
+            // Note: this is O(n) and thus the entire loop is basically O(n^2) but whatevs.
             // We're talking dozens, not millions.
-            if let Some(desired_window_info) = desired_layout.windows.iter().find(|d| d.matches(&window_info)) {
+            if let Some(desired_window_info) = WindowInfo::merge(&rules, &window_info) {
                 debug!(
-                    "Found match for window {:?}/{:?}: {:?}/{:?}",
-                    window_info.owner_name, window_info.name, desired_window_info.owner_name, desired_window_info.name,
+                    "Found match for window {:?}/{:?}: {:?}/{:?} (from {:?})",
+                    window_info.owner_name,
+                    window_info.name,
+                    desired_window_info.owner_name,
+                    desired_window_info.name,
+                    desired_window_info.source,
                 );
                 debug!(
-                    "Current bounds: {}/{:?}, desired: {}/{:?}",
+                    "Current bounds: {:?}/{:?}, desired: {:?}/{:?}",
                     window_info.screen_num, window_info.pos, desired_window_info.screen_num, desired_window_info.pos
                 );
 
@@ -112,10 +223,19 @@ fn restore_layout(path: String) {
                     // If the screen index is higher than the current number of screens, just take the right-most.
                     let current_screen_index = (matching_window.screen_num - 1).min(screens.len() - 1);
                     let current_screen = &screens[current_screen_index];
-                    let desired_screen_index = (desired_window_info.screen_num - 1).min(screens.len() - 1);
+                    // Anything the rules leave out stays as it is: the same screen, or the same position (relative to
+                    // the screen).
+                    let desired_screen_num = desired_window_info.screen_num.unwrap_or(matching_window.screen_num);
+                    // screen_num 0 isn't valid (see `layout validate`), but shouldn't crash.
+                    let desired_screen_index = desired_screen_num.saturating_sub(1).min(screens.len() - 1);
                     let desired_screen = &screens[desired_screen_index];
-                    let current_absolute_bounds = matching_pos.to_absolute(&current_screen);
-                    let desired_absolute_bounds = desired_window_info.pos.to_absolute(&desired_screen);
+                    let desired_pos = desired_window_info.desired_pos(desired_screen, &matching_pos);
+                    let current_absolute_bounds = matching_pos.to_absolute(current_screen, Spacing::default());
+                    let spacing = Spacing {
+                        gaps: desired_layout.gaps.unwrap_or_default(),
+                        margin: desired_window_info.margin.unwrap_or_default(),
+                    };
+                    let desired_absolute_bounds = desired_pos.to_absolute(desired_screen, spacing);
 
                     // Rather than checking for equality, check for "within a couple of pixels" because I've found
                     // that after moving, the window coords don't always exactly match what I sent.
@@ -125,7 +245,9 @@ fn restore_layout(path: String) {
                             window_info.owner_name, window_info.name, current_absolute_bounds, desired_absolute_bounds
                         );
 
-                        move_window(&window_info, matching_window, desired_absolute_bounds);
+                        if let Err(e) = window_system.move_window(matching_window, &desired_absolute_bounds) {
+                            error!("Failed to move {:?}/{:?}: {}", window_info.owner_name, window_info.name, e);
+                        }
                     } else {
                         trace!("No need to move {:?}/{:?}", window_info.owner_name, window_info.name);
                     }
@@ -135,288 +257,935 @@ fn restore_layout(path: String) {
             }
         }
 
-        sleep(Duration::from_millis(500));
+        window_system.wait_for_moves();
     }
+
+    Ok(())
 }
 
-/// Loads the user's layout file.
-fn load_layout_file(path: String) -> Layout {
-    // Don't need the portable "home" crate, because this is MacOs-only.
-    let home: String = env!("HOME").into();
+/// Prints the rules that match the window, most specific first, and the result of merging them.
+fn explain_match(window_info: &WindowInfo, rules: &[&WindowInfo]) {
+    println!("{} / {}:", window_info.owner_name, window_info.name);
 
-    let path: String = Regex::new("^~").unwrap().replace(&path, home).into();
+    let merged = match WindowInfo::merge(rules, window_info) {
+        Some(merged) => merged,
+        None => {
+            println!("    no matching rule");
+            return;
+        }
+    };
+    for rule in rules.iter().filter(|rule| rule.matches(window_info)) {
+        println!(
+            "    {} / {} ({}, from {}): screen {}, {}",
+            rule.owner_name,
+            rule.name,
+            rule.specificity(),
+            rule.source
+                .as_deref()
+                .map_or("?".into(), |source| source.display().to_string()),
+            explain_value(&rule.screen_num),
+            explain_value(&rule.pos)
+        );
+    }
+    println!("  => screen {}, {}", explain_value(&merged.screen_num), explain_value(&merged.pos));
+}
 
-    let file = File::open(&path).expect(&format!("Failed to open {}", path));
-    let reader = BufReader::new(file);
+fn explain_value<T: std::fmt::Debug>(value: &Option<T>) -> String {
+    match value {
+        Some(value) => format!("{:?}", value),
+        None => "unchanged".into(),
+    }
+}
 
-    let desired_layout: Layout =
-        serde_yaml::from_reader(reader).expect(&format!("Failed to parse layout file {}", path));
-    desired_layout
+/// Loads just the specified layout file (with its includes), ready for use.
+#[cfg(test)]
+fn load_layout_file(path: String) -> anyhow::Result<Layout> {
+    load_layout_files(&[LayoutFile {
+        layer: config::Layer::User,
+        path: expand_path(&path)?.into(),
+        required: true,
+    }])
 }
 
-/// Returns a list of the current screens, ordered from left to right.
-fn get_screens() -> Vec<ScreenInfo> {
-    let mut screens = vec![];
-
-    unsafe {
-        let ns_screens = NSScreen::screens(nil);
-        let primary_screen = NSScreen::mainScreen(nil);
-        let primary_frame = primary_screen.frame();
-        let screen_num_key = IdRef::new(NSString::alloc(nil).init_str("NSScreenNumber"));
-
-        for screen in ns_screens.iter() {
-            let frame = screen.frame();
-
-            // I'm going to convert all NSScreen coords (which are bizarrely reversed in the Y axis) to the
-            // same orientation as window coords.
-            let fixed_y = primary_frame.size.height - frame.size.height - frame.origin.y;
-
-            let device_desc = screen.deviceDescription();
-            let screen_id: id = msg_send![device_desc, objectForKey:*screen_num_key];
-            let screen_id: NSUInteger = msg_send![screen_id, unsignedIntegerValue];
-            screens.push(ScreenInfo {
-                screen_id: screen_id as u32,
-                frame: Rect {
-                    x: frame.origin.x as i32,
-                    y: fixed_y as i32,
-                    w: frame.size.width as i32,
-                    h: frame.size.height as i32,
-                },
-            });
+/// Loads every one of the layout files that exists, and merges them into one, ready for use. The files have to be
+/// in order of precedence, lowest first (as returned by `config::layout_files`).
+fn load_layout_files(files: &[LayoutFile]) -> anyhow::Result<Layout> {
+    let mut desired_layout: Option<Layout> = None;
+
+    // The highest precedence file goes first, so that it can take in the others the way it takes in its includes.
+    for file in files.iter().rev() {
+        if !file.path.exists() {
+            if file.required {
+                return Err(
+                    LayoutError::NotFound(format!("The layout file {} doesn't exist", file.path.display())).into()
+                );
+            }
+            debug!("No {} layout file at {}", file.layer, file.path.display());
+            continue;
+        }
+
+        let layout = load_with_includes(&file.path, &mut Vec::new())?;
+        match &mut desired_layout {
+            Some(desired_layout) => desired_layout.merge_included(layout),
+            None => desired_layout = Some(layout),
         }
     }
 
-    if screens.is_empty() {
-        panic!("Unable to enumerate screens.\nPlease add layout to the 'Screen & System Audio Recording' apps\nin System Preferences -> Privacy & Security")
+    let mut desired_layout = desired_layout.ok_or_else(|| {
+        let paths: Vec<String> = files.iter().map(|file| file.path.display().to_string()).collect();
+        LayoutError::NotFound(format!(
+            "No layout file found (looked for {}). Run `layout save > ~/.layout.yaml` to create one.",
+            paths.join(", ")
+        ))
+    })?;
+    desired_layout.resolve_aliases()?;
+    Ok(desired_layout)
+}
+
+/// Checks the layout files (and the files they include) for problems, and prints them. The layout files have to load
+/// cleanly first: if they don't, that's the only problem reported.
+fn validate_layout_files(files: &[LayoutFile], format: ReportFormat) -> anyhow::Result<()> {
+    let problems = match load_layout_files(files) {
+        Ok(layout) => lint_layout_files(&layout.files)?,
+        Err(e) => {
+            // Otherwise the error is only logged, which is all that's needed for the text format.
+            if format == ReportFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&[Problem::from_error(&e)])?);
+            }
+            return Err(e);
+        }
+    };
+
+    match format {
+        ReportFormat::Text => problems.iter().for_each(|problem| println!("{}", problem)),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&problems)?),
     }
 
-    // Sort the screens left-to-right
-    screens.sort_by(|screen1, screen2| screen1.frame.x.cmp(&screen2.frame.x));
+    match problems.len() {
+        0 => {
+            info!("No problems found");
+            Ok(())
+        }
+        1 => Err(LayoutError::Invalid("Found 1 problem in the layout files".into()).into()),
+        count => Err(LayoutError::Invalid(format!("Found {} problems in the layout files", count)).into()),
+    }
+}
+
+/// Checks each of the files separately, as written: a rule in one file that overrides a rule in another is how
+/// layering is meant to work, and isn't a problem.
+fn lint_layout_files(paths: &[PathBuf]) -> anyhow::Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+    let mut linted: Vec<PathBuf> = Vec::new();
+
+    for path in paths {
+        // A file can be included more than once.
+        let canonical_path = path.canonicalize().map_err(|source| LayoutError::read(path, source))?;
+        if linted.contains(&canonical_path) {
+            continue;
+        }
+        linted.push(canonical_path);
+
+        let mut layout = read_layout_file(&path.display().to_string())?;
+        layout.apply_version();
+        // Whatever the file's format, it's linted as YAML.
+        let raw: serde_yaml::Value = format::read_file(path)?;
 
-    screens
+        problems.extend(validate::lint(path, &layout, &raw));
+    }
+    Ok(problems)
 }
 
-/// Returns the current window and screen layout.
-fn get_current_layout(screens: &Vec<ScreenInfo>) -> Layout {
-    let windows = get_windows(screens);
+/// Prints the JSON Schema for layout files.
+fn print_schema() -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(&layout_schema())?);
+    Ok(())
+}
 
-    Layout { windows }
+fn layout_schema() -> schemars::Schema {
+    // Draft 7 is the most widely supported, by yaml-language-server in particular.
+    schemars::generate::SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<Layout>()
 }
 
-/// Returns a list of window owners that we wish to ignore.
-fn owners_to_ignore() -> HashSet<String> {
-    HashSet::from(["Control Center".into(), "Dock".into(), "Window Server".into()])
+/// Prints every place a layout file is looked for, and which files were found and loaded (including the files they
+/// include).
+fn print_config_paths(files: &[LayoutFile]) -> anyhow::Result<()> {
+    for file in files {
+        if !file.path.exists() {
+            println!("{:<8} {} (not found)", file.layer, file.path.display());
+            continue;
+        }
+
+        let layout = load_with_includes(&file.path, &mut Vec::new())?;
+        println!("{:<8} {} (loaded)", file.layer, file.path.display());
+        for included in layout.files.iter().skip(1) {
+            println!("{:<8}   includes {}", "", included.display());
+        }
+    }
+    Ok(())
 }
 
-/// Returns a list of `WindowInfo` for the current desktop windows.
-fn get_windows(screens: &Vec<ScreenInfo>) -> Vec<WindowInfo> {
-    // Use a map of maps here to get a nicely ordered list. Ordered by owner name and then window name.
-    let mut window_map: BTreeMap<String, BTreeMap<String, WindowInfo>> = BTreeMap::new();
-    let owners_to_ignore = owners_to_ignore();
+/// Loads a layout file, and (recursively) the files it includes. Later includes take precedence over earlier ones,
+/// and the including file takes precedence over all of them.
+/// <br>`including` is the chain of files that led to this one, for detecting cycles.
+fn load_with_includes(path: &Path, including: &mut Vec<PathBuf>) -> anyhow::Result<Layout> {
+    let canonical_path = path.canonicalize().map_err(|source| LayoutError::read(path, source))?;
+    if including.contains(&canonical_path) {
+        let chain: Vec<String> = including.iter().map(|path| path.display().to_string()).collect();
+        return Err(LayoutError::Invalid(format!(
+            "{} includes itself: {} -> {}",
+            path.display(),
+            chain.join(" -> "),
+            path.display()
+        ))
+        .into());
+    }
 
-    let cg_window_infos = CGDisplay::window_list_info(
-        display::kCGWindowListExcludeDesktopElements | display::kCGWindowListOptionOnScreenOnly,
-        None,
-    );
+    let mut layout = read_layout_file(&path.display().to_string())?;
+    layout.apply_version();
+    layout.set_source(path);
+    layout.files.push(path.to_path_buf());
 
-    if cg_window_infos.is_none() {
-        error!("Failed to retrieve list of windows.");
-        return Vec::new();
+    including.push(canonical_path);
+    let directory = path.parent().unwrap_or(Path::new("."));
+    for include in layout.include.clone().iter().rev() {
+        let included = load_with_includes(&directory.join(expand_path(include)?), including)?;
+        layout.merge_included(included);
     }
+    including.pop();
 
-    let cg_window_infos = cg_window_infos.unwrap();
+    Ok(layout)
+}
 
-    for cg_window_info in cg_window_infos.iter() {
-        // window_info is a dictionary. Need to recast...
-        let window_dict: CFDictionary<*const c_void, *const c_void> =
-            unsafe { CFDictionary::wrap_under_get_rule(*cg_window_info as CFDictionaryRef) };
+/// Parses a layout file, exactly as written. The format (YAML, JSON or TOML) comes from the file's extension.
+fn read_layout_file(path: &str) -> anyhow::Result<Layout> {
+    format::read_file(Path::new(path))
+}
 
-        let mut window_info = WindowInfo::default();
+/// Expands a leading `~` in the path to the user's home directory, and `$NAME` or `${NAME}` to the value of that
+/// environment variable.
+fn expand_path(path: &str) -> anyhow::Result<String> {
+    let variable = |name: &str| {
+        std::env::var(name).map_err(|_| {
+            LayoutError::Invalid(format!("Can't expand {}: the {} environment variable isn't set", path, name))
+        })
+    };
+
+    let expanded = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", variable("HOME")?, rest),
+        _ => path.to_string(),
+    };
+
+    // Variables are expanded in a single pass, so that a value containing a `$` is left alone.
+    let variable_regex = Regex::new(r"\$(?:\{(\w+)\}|(\w+))").unwrap();
+    let mut result = String::new();
+    let mut end = 0;
+    for captures in variable_regex.captures_iter(&expanded) {
+        let whole = captures.get(0).unwrap();
+        let name = captures.get(1).or(captures.get(2)).unwrap().as_str();
+        result.push_str(&expanded[end..whole.start()]);
+        result.push_str(&variable(name)?);
+        end = whole.end();
+    }
+    result.push_str(&expanded[end..]);
 
-        let owner_name = get_string_from_dict(&window_dict, "kCGWindowOwnerName");
-        let window_name = get_string_from_dict(&window_dict, "kCGWindowName");
+    Ok(result)
+}
 
-        // Skip some obvious windows: empty names, or names in the ignore list.
-        if owner_name.is_empty() || window_name.is_empty() || owners_to_ignore.contains(&owner_name) {
-            continue;
-        }
+/// Returns a list of the current screens, ordered from left to right.
+fn get_screens(window_system: &dyn WindowSystem) -> anyhow::Result<Vec<ScreenInfo>> {
+    let mut screens = window_system.get_screens()?;
+    if screens.is_empty() {
+        return Err(LayoutError::NoScreens.into());
+    }
 
-        window_info.owner_name = Exact(owner_name.clone());
-        window_info.name = Exact(window_name.clone());
-        let bounds = match get_dict_from_dict(&window_dict, "kCGWindowBounds") {
-            Some(value) => value,
-            None => continue,
-        };
+    // Sort the screens left-to-right
+    screens.sort_by_key(|screen| screen.frame.x);
 
-        let process_id: i32 = get_num_from_dict(&window_dict, "kCGWindowOwnerPID");
-        let window_id: u32 = get_num_from_dict(&window_dict, "kCGWindowNumber");
+    Ok(screens)
+}
 
-        let bounds: Rect = CGRect::from_dict_representation(&bounds).unwrap().into();
-        window_info.pos = WindowPos::Pos(bounds.clone());
+/// Returns the current window and screen layout.
+fn get_current_layout(window_system: &dyn WindowSystem, screens: &[ScreenInfo]) -> anyhow::Result<Layout> {
+    // Use a map of maps here to get a nicely ordered list. Ordered by owner name and then window name.
+    let mut window_map: BTreeMap<String, BTreeMap<String, WindowInfo>> = BTreeMap::new();
+
+    for window in window_system.get_windows()? {
+        // Skip some obvious windows: empty names.
+        if window.owner_name.is_empty() || window.name.is_empty() {
+            continue;
+        }
 
         // Skip windows below a certain size
-        if bounds.w <= MIN_WIDTH && bounds.h <= MIN_HEIGHT {
+        if window.bounds.w <= MIN_WIDTH && window.bounds.h <= MIN_HEIGHT {
             continue;
         }
 
         // `bounds` is an absolute position, so convert to a position relative to the containing screen.
-        let (screen_num, adjusted_bounds) = WindowPos::Pos(bounds).to_relative(&screens);
-        window_info.screen_num = screen_num;
-        window_info.pos = WindowPos::Pos(adjusted_bounds.clone());
+        let (screen_num, adjusted_bounds) = WindowPos::Pos(window.bounds).to_relative(screens);
 
-        if !window_map.contains_key(&owner_name) {
-            window_map.insert(owner_name.clone(), BTreeMap::new());
-        }
-        let owner_map = window_map.get_mut(&owner_name).unwrap();
+        let owner_map = window_map.entry(window.owner_name.clone()).or_default();
 
         // There can be multiple windows with the same owner name and window name (e.g. multiple projects opened
-        // in RustRover and they each have a "Find" window open). This is why window_info.ids is a Vector.
-        if let Some(matching_window_info) = owner_map.get(&window_name) {
-            window_info.matching_windows = matching_window_info.matching_windows.clone();
-        } else {
-            window_info.matching_windows = Vec::new();
-        }
+        // in RustRover and they each have a "Find" window open). This is why window_info.matching_windows is a
+        // Vector.
+        let window_info = owner_map.entry(window.name.clone()).or_insert_with(|| WindowInfo {
+            owner_name: Exact(window.owner_name),
+            name: Exact(window.name),
+            ..WindowInfo::default()
+        });
+        window_info.screen_num = Some(screen_num);
+        window_info.pos = Some(WindowPos::Pos(adjusted_bounds.clone()));
+        // So that the position can be scaled to fit a screen of another size.
+        window_info.screen_size = Some(Size::from(&screens[screen_num - 1].frame));
         window_info.matching_windows.push(MatchingWindowInfo {
-            process_id,
-            window_id,
+            process_id: window.process_id,
+            window_id: window.window_id,
             screen_num,
-            bounds: adjusted_bounds.clone(),
+            bounds: adjusted_bounds,
         });
-        owner_map.insert(window_name, window_info);
     }
 
     // Now flatten the map of maps into a vector that is sorted by Owner Name and then Name.
-    let windows: Vec<WindowInfo> = window_map
-        .into_iter()
-        .map(|(_, windows_by_owner)| windows_by_owner)
-        .collect::<Vec<BTreeMap<String, WindowInfo>>>()
-        .into_iter()
-        .flat_map(|windows_by_owner| {
-            windows_by_owner
-                .into_iter()
-                .map(|(_, v)| v)
-                .collect::<Vec<WindowInfo>>()
-        })
+    let windows = window_map
+        .into_values()
+        .flat_map(|windows_by_owner| windows_by_owner.into_values())
         .collect();
 
-    if windows.is_empty() {
-        panic!("Unable to enumerate windows.\nPlease add layout to the 'Screen & System Audio Recording' apps\nin System Preferences -> Privacy & Security")
+    Ok(Layout {
+        windows,
+        ..Layout::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::layout_types::{FracRect, Rect, Scale};
+    use crate::window_system::fake::{FakeMove, FakeWindowSystem};
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
     }
 
-    windows
-}
+    fn sample_layout() -> Layout {
+        load_layout_file(format!("{}/sample-layout.yaml", env!("CARGO_MANIFEST_DIR"))).unwrap()
+    }
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rect {
+        Rect { x, y, w, h }
+    }
+
+    #[test]
+    fn restore_moves_matching_windows() {
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+
+        restore_layout(&window_system, &sample_layout(), None, false).unwrap();
 
-/// Moves the specified window to the desired location.
-fn move_window(window_info: &WindowInfo, matching_window: &MatchingWindowInfo, desired_absolute_bounds: Rect) {
-    if let Ok(axwindow) = get_axwindow(matching_window.process_id, matching_window.window_id) {
-        trace!(
-            "Found axwindow for {:?}/{:?}/{:?}/{:?}",
-            window_info.owner_name,
-            window_info.name,
-            matching_window.process_id,
-            matching_window.window_id
+        // Windows are visited in owner/name order. Terminal is already maxed on screen 2, and there's no rule
+        // for Calculator, so neither of them moves.
+        assert_eq!(
+            window_system.moves(),
+            vec![
+                FakeMove {
+                    window_id: 3,
+                    bounds: rect(2110, 1240, 400, 143),
+                },
+                FakeMove {
+                    window_id: 2,
+                    bounds: rect(2560, 400, 1646, 458),
+                },
+                FakeMove {
+                    window_id: 7,
+                    bounds: rect(2560, 400, 1646, 458),
+                },
+                FakeMove {
+                    window_id: 1,
+                    bounds: rect(0, 0, 2560, 1440),
+                },
+            ]
         );
+        assert_eq!(window_system.bounds_of(5), Some(rect(10, 10, 300, 400)));
+    }
 
-        let mut cg_pos: CGPoint = desired_absolute_bounds.origin();
-        let position = unsafe {
-            AXValueCreate(
-                kAXValueTypeCGPoint,
-                // What a masterpiece of ugliness:
-                &mut cg_pos as *mut _ as *mut c_void,
-            )
+    #[test]
+    fn restore_is_idempotent() {
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+
+        restore_layout(&window_system, &sample_layout(), None, false).unwrap();
+        let moves = window_system.moves().len();
+        restore_layout(&window_system, &sample_layout(), None, false).unwrap();
+
+        assert_eq!(window_system.moves().len(), moves);
+    }
+
+    #[test]
+    fn restore_uses_profile_rules_then_top_level_rules() {
+        let layout = load_layout_file(fixture("profiles-layout.yaml").display().to_string()).unwrap();
+
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+        restore_layout(&window_system, &layout, Some("docked"), false).unwrap();
+        assert_eq!(window_system.bounds_of(1), Some(rect(2560, 0, 1920, 1080)));
+        assert_eq!(window_system.bounds_of(4), Some(rect(-1512, 400, 1512, 982)));
+
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+        restore_layout(&window_system, &layout, Some("laptop"), false).unwrap();
+        assert_eq!(window_system.bounds_of(1), Some(rect(-1512, 400, 1512, 982)));
+        assert_eq!(window_system.bounds_of(4), Some(rect(-1512, 400, 756, 982)));
+    }
+
+    #[test]
+    fn restore_selects_profile_for_current_screens() {
+        let layout = load_layout_file(fixture("profiles-layout.yaml").display().to_string()).unwrap();
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+
+        restore_layout(&window_system, &layout, None, false).unwrap();
+
+        assert_eq!(window_system.bounds_of(1), Some(rect(2560, 0, 1920, 1080)));
+    }
+
+    #[test]
+    fn more_specific_rules_win_regardless_of_order() {
+        let mut layout: Layout = serde_yaml::from_str(
+            r#"
+            version: 2
+            windows:
+              - owner_name: RustRover
+                name: !Glob "*"
+                screen_num: 1
+                pos: !Maxed
+              - owner_name: RustRover
+                name: !Regex "layout-rust .*"
+                screen_num: 1
+                pos: !Left 0.5
+              - owner_name: RustRover
+                name: !Regex "layout-.*"
+                screen_num: 1
+                pos: !Right 0.5
+              - owner_name: RustRover
+                name: Find
+                screen_num: 3
+                pos: !Maxed
+              - owner_name: !Regex .*
+                name: !Regex .*
+                screen_num: 2
+                pos: !Maxed
+                priority: -1
+              - owner_name: Microsoft Outlook
+                name: !Glob "*"
+                screen_num: 2
+                pos: !Top 0.5
+                priority: 1
+              - owner_name: Microsoft Outlook
+                name: 1 Reminder
+                screen_num: 2
+                pos: !Maxed
+            "#,
+        )
+        .unwrap();
+        layout.apply_version();
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+
+        restore_layout(&window_system, &layout, None, false).unwrap();
+
+        // The pattern with more literal characters wins.
+        assert_eq!(window_system.bounds_of(1), Some(rect(-1512, 400, 756, 982)));
+        // The exact match wins.
+        assert_eq!(window_system.bounds_of(2), Some(rect(2560, 0, 1920, 1080)));
+        // The explicit priority wins.
+        assert_eq!(window_system.bounds_of(3), Some(rect(0, 0, 2560, 720)));
+        // A negative priority makes a catch-all rule a fallback.
+        assert_eq!(window_system.bounds_of(5), Some(rect(0, 0, 2560, 1440)));
+    }
+
+    #[test]
+    fn matching_rules_are_merged() {
+        let layout: Layout = serde_yaml::from_str(
+            r#"
+            version: 2
+            windows:
+              - owner_name: RustRover
+                name: !Glob "*"
+                screen_num: 3
+              - owner_name: RustRover
+                name: layout-rust – main.rs
+                pos: !Maxed
+            "#,
+        )
+        .unwrap();
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+
+        restore_layout(&window_system, &layout, None, false).unwrap();
+
+        // The screen comes from the first rule, and the position from the second.
+        assert_eq!(window_system.bounds_of(1), Some(rect(2560, 0, 1920, 1080)));
+        // No rule sets the position of the Find windows, so they keep their position relative to the screen: one
+        // moves to screen 3, and the other is already there.
+        assert_eq!(window_system.bounds_of(2), Some(rect(2672, 100, 600, 400)));
+        assert_eq!(window_system.bounds_of(7), Some(rect(2600, 50, 600, 400)));
+        assert_eq!(window_system.moves().iter().map(|m| m.window_id).collect::<Vec<_>>(), vec![2, 1]);
+    }
+
+    #[test]
+    fn included_files_are_merged() {
+        let layout = load_layout_file(fixture("include/main.yaml").display().to_string()).unwrap();
+        let sources: Vec<_> = layout
+            .windows
+            .iter()
+            .map(|rule| rule.source.as_ref().unwrap().file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                "main.yaml",
+                "outlook.yaml",
+                "jetbrains.yaml",
+                "jetbrains.yaml",
+                "jetbrains.yaml",
+                "jetbrains.yaml"
+            ]
+        );
+
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+        restore_layout(&window_system, &layout, None, false).unwrap();
+
+        // jetbrains.yaml is a version 1 file, so its plain strings are still regexes.
+        assert_eq!(window_system.bounds_of(1), Some(rect(0, 0, 2560, 1440)));
+        // main.yaml's rule beats the included one...
+        assert_eq!(window_system.bounds_of(2), Some(rect(-1512, 400, 1512, 982)));
+        // ...and so does its alias.
+        assert_eq!(window_system.bounds_of(3), Some(rect(2110, 1240, 400, 143)));
+    }
+
+    #[test]
+    fn layout_files_can_be_json_or_toml() {
+        let dir = tempfile::tempdir().unwrap();
+        let sample = read_layout_file(&format!("{}/sample-layout.yaml", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+        restore_layout(&window_system, &sample_layout(), None, false).unwrap();
+        let expected_moves = window_system.moves();
+
+        for (format, name) in [(LayoutFormat::Json, "layout.json"), (LayoutFormat::Toml, "layout.toml")] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, format.to_string(&sample).unwrap()).unwrap();
+
+            let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+            restore_layout(&window_system, &load_layout_file(path.display().to_string()).unwrap(), None, false)
+                .unwrap();
+
+            assert_eq!(window_system.moves(), expected_moves, "{}", name);
+        }
+    }
+
+    #[test]
+    fn map_style_positions_and_patterns_work_without_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layout.toml");
+        std::fs::write(
+            &path,
+            r#"
+            version = 2
+
+            [positions]
+            half = { left = 0.5 }
+
+            [[windows]]
+            owner_name = "RustRover"
+            name = { glob = "layout-*" }
+            screen_num = 1
+            pos = "$half"
+
+            [[windows]]
+            owner_name = { Regex = "Micro.*" }
+            name = "1 Reminder"
+            screen_num = 2
+            pos = { Pos = "-450,-200,400,143" }
+
+            [[windows]]
+            owner_name = "Terminal"
+            name = "bash"
+            screen_num = 3
+            pos = "maxed"
+            "#,
+        )
+        .unwrap();
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+
+        restore_layout(&window_system, &load_layout_file(path.display().to_string()).unwrap(), None, false).unwrap();
+
+        assert_eq!(window_system.bounds_of(1), Some(rect(-1512, 400, 756, 982)));
+        assert_eq!(window_system.bounds_of(3), Some(rect(2110, 1240, 400, 143)));
+        assert_eq!(window_system.bounds_of(4), Some(rect(2560, 0, 1920, 1080)));
+    }
+
+    #[test]
+    fn include_cycles_are_an_error() {
+        let error = load_layout_file(fixture("include/cycle-a.yaml").display().to_string()).unwrap_err();
+
+        assert!(error.to_string().contains("cycle-a.yaml includes itself"), "{}", error);
+    }
+
+    #[test]
+    fn layers_are_merged_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, yaml: &str| {
+            std::fs::write(dir.path().join(name), yaml).unwrap();
+            dir.path().join(name)
         };
-        let result = unsafe {
-            AXUIElementSetAttributeValue(
-                axwindow,
-                CFString::new(kAXPositionAttribute).as_concrete_TypeRef(),
-                position as _,
+        let rule = |name: &str, screen_num: usize| {
+            format!(
+                "version: 2\nwindows:\n  - owner_name: RustRover\n    name: {}\n    screen_num: {}\n",
+                name, screen_num
             )
         };
-        if result != kAXErrorSuccess {
-            error!("AXUIElementSetAttributeValue(kAXPositionAttribute) failed: {:?}", result);
-            return;
+        let files = [
+            LayoutFile {
+                layer: config::Layer::System,
+                path: write("system.yaml", &format!("{}\n{}", rule("Find", 1), "aliases:\n  shared: Find\n")),
+                required: false,
+            },
+            LayoutFile {
+                layer: config::Layer::Xdg,
+                path: dir.path().join("missing.yaml"),
+                required: false,
+            },
+            LayoutFile {
+                layer: config::Layer::User,
+                path: write("user.yaml", &rule("Find", 2)),
+                required: true,
+            },
+            LayoutFile {
+                layer: config::Layer::Host,
+                path: write("host.yaml", &rule("$shared", 3)),
+                required: false,
+            },
+        ];
+
+        let layout = load_layout_files(&files).unwrap();
+
+        // The host file comes first, and can use the system file's alias.
+        let screens: Vec<_> = layout.windows.iter().map(|rule| rule.screen_num.unwrap()).collect();
+        assert_eq!(screens, vec![3, 2, 1]);
+        assert!(matches!(&layout.windows[0].name, Exact(name) if name == "Find"));
+        assert_eq!(layout.files.len(), 3);
+
+        assert!(load_layout_files(&files[1..2]).is_err());
+    }
+
+    #[test]
+    fn missing_required_layout_file_is_an_error() {
+        let error = load_layout_file("/nonexistent/layout.yaml".into()).unwrap_err();
+
+        assert_eq!(error.to_string(), "The layout file /nonexistent/layout.yaml doesn't exist");
+        assert_eq!(error::exit_code(&error), 2);
+    }
+
+    #[test]
+    fn sample_layout_has_no_problems() {
+        let problems = lint_layout_files(&sample_layout().files).unwrap();
+
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn schema_describes_the_layout_file() {
+        let schema = serde_json::to_value(layout_schema()).unwrap();
+        let definitions = &schema["definitions"];
+
+        for name in ["WindowInfo", "WindowPos", "Rect", "Pattern", "Profile"] {
+            assert!(definitions.get(name).is_some(), "{} is missing", name);
+        }
+        let rect = Regex::new(definitions["Rect"]["pattern"].as_str().unwrap()).unwrap();
+        assert!(rect.is_match("-450,-200,400,143"));
+        assert!(!rect.is_match("1,2,3"));
+
+        // The snake_case names that can be used in JSON and TOML files are in there too.
+        for (definition, names) in [
+            ("WindowPos", &["Maxed", "maxed", "BottomRight", "bottom_right", "grid"][..]),
+            ("Pattern", &["Glob", "glob", "exact"][..]),
+        ] {
+            let text = definitions[definition].to_string();
+            for name in names {
+                assert!(text.contains(&format!("\"{}\"", name)), "{}: {} is missing", definition, name);
+            }
         }
+    }
 
-        let mut cg_size: CGSize = desired_absolute_bounds.size();
-        let size = unsafe { AXValueCreate(kAXValueTypeCGSize, &mut cg_size as *mut _ as *mut c_void) };
+    #[test]
+    fn malformed_rects_are_parse_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layout.yaml");
+        std::fs::write(&path, "version: 2\nwindows:\n  - owner_name: Slack\n    pos: !Pos 1,2,3\n").unwrap();
 
-        let result = unsafe {
-            AXUIElementSetAttributeValue(axwindow, CFString::new(kAXSizeAttribute).as_concrete_TypeRef(), size as _)
-        };
-        if result != kAXErrorSuccess {
-            error!("AXUIElementSetAttributeValue(kAXSizeAttribute) failed: {:?}", result);
+        let error = load_layout_file(path.to_string_lossy().into()).unwrap_err();
+
+        match error.downcast_ref::<LayoutError>() {
+            Some(LayoutError::Parse { line, message, .. }) => {
+                assert_eq!(*line, 4);
+                assert!(message.contains("Invalid rect '1,2,3'"), "{}", message);
+            }
+            _ => panic!("Expected a parse error, got: {:#}", error),
         }
+        assert_eq!(error::exit_code(&error), 4);
     }
-}
 
-/// Given an Owner ID and Window ID from the `CGWindowList` API, returns the corresponding `AXUIElementRef` to
-/// use with the Accessibility API.
-/// <br>(We need this because, annoyingly, we enumerate desktop windows using the CGWindowList API,
-/// and yet we have to use an entirely different API -- the Accessibility API -- to actually move the
-/// windows).
-fn get_axwindow(owner_id: i32, window_id: u32) -> anyhow::Result<AXUIElementRef> {
-    let ax_application = unsafe { AXUIElementCreateApplication(owner_id) };
-    let mut windows_ref: CFTypeRef = std::ptr::null();
+    #[test]
+    fn no_screens_is_an_error() {
+        let window_system = FakeWindowSystem::new(Default::default());
 
-    if ax_application.is_null() {
-        return Err(anyhow!("Failed to get application handle."));
+        let error = get_screens(&window_system).unwrap_err();
+
+        assert!(matches!(error.downcast_ref(), Some(LayoutError::NoScreens)));
+        assert_eq!(error::exit_code(&error), 7);
+    }
+
+    #[test]
+    fn paths_are_expanded_at_runtime() {
+        let home = std::env::var("HOME").unwrap();
+        let path = std::env::var("PATH").unwrap();
+
+        assert_eq!(expand_path("~/.layout.yaml").unwrap(), format!("{}/.layout.yaml", home));
+        assert_eq!(expand_path("~").unwrap(), home);
+        assert_eq!(expand_path("/srv/~backup").unwrap(), "/srv/~backup");
+        assert_eq!(expand_path("$HOME/layouts/${PATH}.yaml").unwrap(), format!("{}/layouts/{}.yaml", home, path));
+        assert!(expand_path("$LAYOUT_TEST_UNDEFINED_VARIABLE/layout.yaml").is_err());
     }
 
-    if unsafe {
-        AXUIElementCopyAttributeValue(
-            ax_application,
-            CFString::new(kAXWindowsAttribute).as_concrete_TypeRef(),
-            &mut windows_ref as *mut CFTypeRef,
+    #[test]
+    fn best_matching_profile_wins() {
+        let screens = get_screens(&FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap()).unwrap();
+        let layout: Layout = serde_yaml::from_str(
+            r#"
+            profiles:
+              home:
+                screens:
+                  resolutions: [1512x982, 3840x2160]
+                windows: []
+              office:
+                screens:
+                  resolutions: [1512x982, 2560x1440, 2560x1440]
+                windows: []
+              office-by-id:
+                screens:
+                  ids: [3]
+                windows: []
+              elsewhere:
+                screens:
+                  count: 2
+                windows: []
+            "#,
         )
-    } != kAXErrorSuccess
-    {
-        unsafe {
-            CFRelease(ax_application.cast());
-        }
+        .unwrap();
 
-        return Err(anyhow!("Failed to get application attribute."));
+        assert_eq!(layout.select_profile(&screens), Some("office"));
+        assert_eq!(layout.select_profile(&screens[..1]), Some("home"));
+        assert_eq!(layout.select_profile(&screens[2..]), Some("office-by-id"));
+        assert_eq!(Layout::default().select_profile(&screens), None);
     }
 
-    if windows_ref.is_null() {
-        unsafe {
-            CFRelease(windows_ref.cast());
-            CFRelease(ax_application.cast());
-        }
+    #[test]
+    fn restore_fails_for_unknown_profile() {
+        let layout = load_layout_file(fixture("profiles-layout.yaml").display().to_string()).unwrap();
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
 
-        return Err(anyhow!("Failed to get application attribute."));
+        assert!(restore_layout(&window_system, &layout, Some("presenting"), false).is_err());
+        assert!(window_system.moves().is_empty());
     }
 
-    let windows_ref = windows_ref as id;
+    #[test]
+    fn failed_saves_are_write_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing/layout.yaml").display().to_string();
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
 
-    let count = unsafe { NSArray::count(windows_ref) };
+        let error = save_layout(&window_system, path, Some("laptop".into()), SaveOptions::default()).unwrap_err();
 
-    for i in 0..count {
-        let ax_window = unsafe { NSArray::objectAtIndex(windows_ref, i) };
+        assert!(matches!(error.downcast_ref::<LayoutError>(), Some(LayoutError::Write { .. })), "{:#}", error);
+        assert!(error.to_string().starts_with("Failed to write"), "{:#}", error);
+    }
 
-        let ax_window_id = {
-            let mut id: CGWindowID = 0;
-            if unsafe { _AXUIElementGetWindow(ax_window as AXUIElementRef, &mut id) } != kAXErrorSuccess {
-                continue;
-            }
-            id
+    #[test]
+    fn save_profile_replaces_only_that_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layout.yaml").display().to_string();
+        std::fs::copy(fixture("profiles-layout.yaml"), &path).unwrap();
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+
+        save_layout(&window_system, path.clone(), Some("laptop".into()), SaveOptions::default()).unwrap();
+        save_layout(&window_system, path.clone(), Some("presenting".into()), SaveOptions::default()).unwrap();
+
+        let layout = load_layout_file(path).unwrap();
+        assert_eq!(layout.windows.len(), 1);
+        assert_eq!(layout.profiles["docked"].windows.len(), 1);
+        assert_eq!(layout.profiles["laptop"].windows.len(), 5);
+        assert_eq!(layout.profiles["presenting"].windows.len(), 5);
+
+        // New profiles get the current screens' signature; existing ones keep theirs.
+        let signature = layout.profiles["presenting"].screens.clone().unwrap();
+        assert_eq!(signature.count, Some(3));
+        assert_eq!(signature.ids, vec![1, 2, 3]);
+        assert_eq!(layout.profiles["laptop"].screens.as_ref().unwrap().count, Some(1));
+    }
+
+    #[test]
+    fn profiles_saved_to_a_version_1_file_match_exactly() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layout.yaml").display().to_string();
+        std::fs::copy(fixture("profiles-layout.yaml"), &path).unwrap();
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+
+        save_layout(&window_system, path.clone(), Some("laptop".into()), SaveOptions::default()).unwrap();
+
+        // A plain `Find` would be an unanchored regex in a version 1 file, and the file's own rules still are.
+        let layout = load_layout_file(path).unwrap();
+        assert_eq!(layout.version, 1);
+        let windows = &layout.profiles["laptop"].windows;
+        let find = windows.iter().find(|rule| rule.name.to_string() == "Find").unwrap();
+        assert!(find.name.matches("Find"));
+        assert!(!find.name.matches("Find in Files"));
+        assert!(layout.windows[0].name.matches("bash"));
+    }
+
+    #[test]
+    fn gaps_and_margins_space_out_computed_positions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layout.yaml");
+        std::fs::write(
+            &path,
+            r#"
+            version: 2
+            gaps:
+              outer: 8
+              inner: 16
+            windows:
+              - owner_name: !Glob "*"
+                name: !Glob "*"
+                margin: 4
+              - owner_name: RustRover
+                name: layout-rust – main.rs
+                screen_num: 3
+                pos: !Left 0.5
+              - owner_name: Terminal
+                name: bash
+                screen_num: 3
+                pos: !Right 0.5
+            "#,
+        )
+        .unwrap();
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+
+        restore_layout(&window_system, &load_layout_file(path.display().to_string()).unwrap(), None, false).unwrap();
+
+        // The margin comes from the catch-all rule, but windows that stay where they are aren't shrunk by it.
+        assert_eq!(window_system.bounds_of(1), Some(rect(2572, 12, 936, 1056)));
+        assert_eq!(window_system.bounds_of(4), Some(rect(3532, 12, 936, 1056)));
+        assert_eq!(window_system.bounds_of(5), Some(rect(10, 10, 300, 400)));
+    }
+
+    #[test]
+    fn relative_layouts_scale_with_the_screen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layout.yaml").display().to_string();
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+        let options = SaveOptions {
+            relative: true,
+            ..SaveOptions::default()
         };
 
-        if ax_window_id == window_id {
-            unsafe {
-                CFRetain(ax_window.cast());
-                CFRelease(windows_ref.cast());
-                CFRelease(ax_application.cast());
-            }
+        save_layout(&window_system, path.clone(), Some("relative".into()), options).unwrap();
+        let layout = load_layout_file(path).unwrap();
 
-            // return Ok(unsafe { AXUIElement::wrap_under_create_rule(ax_window as AXUIElementRef) });
-            return Ok(ax_window as AXUIElementRef);
+        let terminal = &layout.profiles["relative"].windows[4];
+        assert!(
+            matches!(terminal.pos, Some(WindowPos::Frac(frac)) if frac == FracRect { x: 0.0, y: 0.0, w: 1.0, h: 1.0 })
+        );
+        restore_layout(&window_system, &layout, Some("relative"), false).unwrap();
+        // Only one of the two Find windows moves, since a saved layout has one position for each owner and name.
+        assert_eq!(window_system.moves().iter().map(|m| m.window_id).collect::<Vec<_>>(), vec![2]);
+
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens-2x.yaml")).unwrap();
+        restore_layout(&window_system, &layout, Some("relative"), false).unwrap();
+        assert_eq!(window_system.bounds_of(3), Some(rect(5200, 1800, 800, 286)));
+    }
+
+    #[test]
+    fn saved_positions_scale_to_other_screen_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layout.yaml").display().to_string();
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+        save_layout(&window_system, path.clone(), Some("saved".into()), SaveOptions::default()).unwrap();
+        let mut layout = load_layout_file(path).unwrap();
+
+        let outlook = &mut layout.profiles.get_mut("saved").unwrap().windows[1];
+        assert_eq!(outlook.screen_size, Some(Size { w: 1920, h: 1080 }));
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens-2x.yaml")).unwrap();
+        restore_layout(&window_system, &layout, Some("saved"), false).unwrap();
+        assert_eq!(window_system.bounds_of(3), Some(rect(5200, 1800, 800, 286)));
+
+        for (scale, expected) in [
+            (Scale::KeepSize, rect(5400, 1872, 400, 143)),
+            (Scale::None, rect(5160, 900, 400, 143)),
+        ] {
+            layout.profiles.get_mut("saved").unwrap().windows[1].scale = Some(scale);
+            let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens-2x.yaml")).unwrap();
+            restore_layout(&window_system, &layout, Some("saved"), false).unwrap();
+            assert_eq!(window_system.bounds_of(3), Some(expected), "{:?}", scale);
         }
     }
 
-    unsafe {
-        CFRelease(windows_ref.cast());
-        CFRelease(ax_application.cast());
+    #[test]
+    fn current_layout_is_relative_to_screens() {
+        let window_system = FakeWindowSystem::from_fixture(&fixture("three-screens.yaml")).unwrap();
+        let screens = get_screens(&window_system).unwrap();
+
+        let layout = get_current_layout(&window_system, &screens).unwrap();
+
+        let names: Vec<String> = layout
+            .windows
+            .iter()
+            .map(|window| format!("{}/{}", window.owner_name, window.name))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "Calculator/Calculator",
+                "Microsoft Outlook/1 Reminder",
+                "RustRover/Find",
+                "RustRover/layout-rust – main.rs",
+                "Terminal/bash",
+            ]
+        );
+
+        let find = &layout.windows[2];
+        assert_eq!(
+            find.matching_windows,
+            vec![
+                MatchingWindowInfo {
+                    process_id: 100,
+                    window_id: 2,
+                    screen_num: 1,
+                    bounds: rect(112, 100, 600, 400),
+                },
+                MatchingWindowInfo {
+                    process_id: 100,
+                    window_id: 7,
+                    screen_num: 3,
+                    bounds: rect(40, 50, 600, 400),
+                },
+            ]
+        );
     }
 
-    Err(anyhow!("Window not found"))
+    #[test]
+    fn fake_desktop_loads_from_json() {
+        let window_system = FakeWindowSystem::from_fixture(&fixture("one-screen.json")).unwrap();
+
+        assert_eq!(window_system.get_screens().unwrap()[0].frame, rect(0, 0, 1920, 1080));
+        assert_eq!(window_system.get_windows().unwrap()[0].bounds, rect(100, 100, 800, 600));
+    }
 }
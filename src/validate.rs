@@ -0,0 +1,631 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::error::LayoutError;
+use crate::layout_types::{Layout, Length, MaybeRegex, Rect, ScreenInfo, Size, Spacing, WindowInfo, WindowPos};
+
+// Characters that suggest that a plain (and so, exact) string in a version 2 layout file was meant to be a pattern.
+const PATTERN_CHARS: &[char] = &['*', '?', '[', '|', '\\'];
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    // Something that can't work as written, e.g. `screen_num: 0`.
+    Error,
+    // Something that works, but probably not the way it was meant to, e.g. a rule that never takes effect.
+    Warning,
+}
+
+/// A problem found in a layout file by `layout validate`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    pub severity: Severity,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    // Where in the file the problem is, e.g. `windows[2]` or `profiles.docked.windows[0]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(severity: Severity, file: &Path, location: String, message: String) -> Self {
+        Problem {
+            severity,
+            file: Some(file.to_path_buf()),
+            line: None,
+            column: None,
+            location: Some(location),
+            message,
+        }
+    }
+
+    /// A layout that couldn't be loaded at all, e.g. because it isn't valid YAML.
+    pub fn from_error(error: &anyhow::Error) -> Self {
+        let (file, line, column, message) = match error.downcast_ref::<LayoutError>() {
+            Some(LayoutError::Parse {
+                path,
+                line,
+                column,
+                message,
+            }) => (Some(path.clone()), Some(*line), Some(*column), message.clone()),
+            _ => (None, None, None, error.to_string()),
+        };
+
+        Problem {
+            severity: Severity::Error,
+            file,
+            line,
+            column,
+            location: None,
+            message,
+        }
+    }
+}
+
+// Like a compiler message, e.g. "/home/me/.layout.yaml: windows[2]: warning: ...".
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let position: Vec<String> = [
+            self.file.as_ref().map(|file| file.display().to_string()),
+            self.line.map(|line| line.to_string()),
+            self.column.map(|column| column.to_string()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if !position.is_empty() {
+            write!(f, "{}: ", position.join(":"))?;
+        }
+        if let Some(location) = &self.location {
+            write!(f, "{}: ", location)?;
+        }
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}", severity, self.message)
+    }
+}
+
+/// Checks a single layout file for problems. `layout` is the file as written (with its version applied, but without
+/// its includes or aliases resolved), and `raw` is the same file as plain YAML, which still shows which patterns
+/// were written without a tag.
+pub fn lint(file: &Path, layout: &Layout, raw: &Value) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    for (name, pos) in &layout.positions {
+        lint_pos(pos, &mut |message| {
+            problems.push(Problem::new(Severity::Error, file, format!("positions.{}", name), message))
+        });
+    }
+
+    if let Some(gaps) = layout.gaps.filter(|gaps| gaps.outer < 0 || gaps.inner < 0) {
+        problems.push(Problem::new(
+            Severity::Error,
+            file,
+            "gaps".into(),
+            format!("The gaps (outer {}, inner {}) can't be negative", gaps.outer, gaps.inner),
+        ));
+    }
+
+    // The screen sizes that each rule can be checked against: its profile's resolutions, or for the top-level rules,
+    // every profile's.
+    let all_resolutions: Vec<Size> = layout
+        .profiles
+        .values()
+        .filter_map(|profile| profile.screens.as_ref())
+        .flat_map(|screens| screens.resolutions.iter().copied())
+        .collect();
+    let profile_rules = layout.profiles.iter().flat_map(|(name, profile)| {
+        let location = format!("profiles.{}.windows", name);
+        let resolutions = profile
+            .screens
+            .as_ref()
+            .map_or(&[][..], |screens| screens.resolutions.as_slice());
+        profile
+            .windows
+            .iter()
+            .enumerate()
+            .map(move |(index, rule)| (format!("{}[{}]", location, index), rule, resolutions))
+    });
+    let top_level_rules = layout
+        .windows
+        .iter()
+        .enumerate()
+        .map(|(index, rule)| (format!("windows[{}]", index), rule, all_resolutions.as_slice()));
+    for (location, rule, resolutions) in top_level_rules.chain(profile_rules) {
+        if rule.screen_num == Some(0) {
+            problems.push(Problem::new(
+                Severity::Error,
+                file,
+                location.clone(),
+                "screen_num 0 doesn't exist: screens are numbered from 1 (the left-most)".into(),
+            ));
+        }
+        if let Some(margin) = rule.margin.filter(|margin| *margin < 0) {
+            problems.push(Problem::new(
+                Severity::Error,
+                file,
+                location.clone(),
+                format!("The margin {} can't be negative", margin),
+            ));
+        }
+        if let Some(pos) = &rule.pos {
+            lint_pos(pos, &mut |message| problems.push(Problem::new(Severity::Error, file, location.clone(), message)));
+        }
+        if let Some(message) = lint_spacing(layout, rule, resolutions) {
+            problems.push(Problem::new(Severity::Error, file, location.clone(), message));
+        }
+    }
+
+    lint_plain_patterns(file, layout.version, raw, &mut problems);
+
+    // A rule can only be shadowed by the rules that are tried along with it: the top-level rules by each other, and
+    // a profile's rules by each other and by the top-level rules.
+    let profiles = std::iter::once(None).chain(layout.profiles.keys().map(|name| Some(name.as_str())));
+    for profile in profiles {
+        let Ok(rules) = layout.rules(profile) else {
+            continue;
+        };
+        // The top-level rules are only checked once, along with each other.
+        let own_rules = match profile {
+            Some(name) => &layout.profiles[name].windows,
+            None => &layout.windows,
+        };
+        for (index, rule) in rules.iter().enumerate() {
+            let Some(location) = locate(layout, profile, rule).filter(|_| contains(own_rules, rule)) else {
+                continue;
+            };
+            if let Some(earlier) = rules[..index].iter().find(|earlier| shadows(earlier, rule)) {
+                let earlier_location = locate(layout, profile, earlier).unwrap_or_default();
+                let message = match is_duplicate(earlier, rule) {
+                    true => format!("{} duplicates {}", describe(rule), earlier_location),
+                    false => format!(
+                        "{} never takes effect: {} is tried first, matches every window it does, and sets everything \
+                         it sets",
+                        describe(rule),
+                        earlier_location
+                    ),
+                };
+                problems.push(Problem::new(Severity::Warning, file, location, message));
+            }
+        }
+    }
+
+    problems
+}
+
+fn lint_pos(pos: &WindowPos, report: &mut dyn FnMut(String)) {
+    match pos {
+        WindowPos::Pos(rect) if rect.w <= 0 || rect.h <= 0 => report(format!(
+            "The rect {},{},{},{} is malformed: its width and height have to be more than 0",
+            rect.x, rect.y, rect.w, rect.h
+        )),
+        WindowPos::Left(fraction)
+        | WindowPos::Right(fraction)
+        | WindowPos::Top(fraction)
+        | WindowPos::Bottom(fraction)
+            if !(*fraction > 0.0 && *fraction <= 1.0) =>
+        {
+            report(format!("{:?} has to be more than 0 and at most 1: it's a fraction of the screen", pos))
+        }
+        WindowPos::Frac(frac)
+            if [frac.x, frac.y, frac.w, frac.h]
+                .iter()
+                .any(|fraction| !(0.0..=1.0).contains(fraction))
+                || frac.w == 0.0
+                || frac.h == 0.0 =>
+        {
+            report(format!(
+                "The fractional rect {},{},{},{} is malformed: its values are fractions of the screen, between 0 and \
+                 1, and its width and height have to be more than 0",
+                frac.x, frac.y, frac.w, frac.h
+            ))
+        }
+        // `layout save --relative` rounds to 4 decimal places, which can add up to a little more than 1.
+        WindowPos::Frac(frac) if frac.x + frac.w > 1.001 || frac.y + frac.h > 1.001 => report(format!(
+            "The fractional rect {},{},{},{} goes past the edge of the screen: x + w and y + h can't be more than 1",
+            frac.x, frac.y, frac.w, frac.h
+        )),
+        WindowPos::Center(anchored)
+        | WindowPos::TopLeft(anchored)
+        | WindowPos::TopRight(anchored)
+        | WindowPos::BottomLeft(anchored)
+        | WindowPos::BottomRight(anchored)
+            if !is_size(anchored.w) || !is_size(anchored.h) =>
+        {
+            report(format!(
+                "The size {},{} is malformed: the width and height have to be more than 0 pixels, or fractions of \
+                 the screen between 0 and 1",
+                anchored.w, anchored.h
+            ))
+        }
+        WindowPos::Grid(grid) if grid.columns == 0 || grid.rows == 0 => {
+            report("The grid has no cells: it needs at least 1 column and 1 row".into())
+        }
+        WindowPos::Grid(grid) if grid.column == 0 || grid.row == 0 || grid.column_span == 0 || grid.row_span == 0 => {
+            report("The grid's columns and rows are numbered from 1, and spans have to be at least 1".into())
+        }
+        // Widened, so that huge (but valid-looking) values can't overflow.
+        WindowPos::Grid(grid) if grid.column as u64 + grid.column_span as u64 - 1 > grid.columns as u64 => {
+            report(format!(
+                "Column {} (spanning {}) doesn't fit in a grid of {} columns",
+                grid.column, grid.column_span, grid.columns
+            ))
+        }
+        WindowPos::Grid(grid) if grid.row as u64 + grid.row_span as u64 - 1 > grid.rows as u64 => {
+            report(format!("Row {} (spanning {}) doesn't fit in a grid of {} rows", grid.row, grid.row_span, grid.rows))
+        }
+        _ => {}
+    }
+}
+
+/// Checks that the gaps and the rule's margin leave room for its window, on the screens (of the specified
+/// resolutions, left to right) it can end up on. Returns the problem, if there is one.
+fn lint_spacing(layout: &Layout, rule: &WindowInfo, resolutions: &[Size]) -> Option<String> {
+    let spacing = Spacing {
+        gaps: layout.gaps.unwrap_or_default(),
+        margin: rule.margin.unwrap_or_default(),
+    };
+    let pos = match &rule.pos {
+        Some(WindowPos::Alias(name)) => layout.positions.get(name)?,
+        Some(pos) => pos,
+        None => return None,
+    };
+    // Neither applies to `Pos`.
+    if spacing == Spacing::default() || matches!(pos, WindowPos::Pos(_)) {
+        return None;
+    }
+    let resolutions = match rule
+        .screen_num
+        .and_then(|screen_num| resolutions.get(screen_num.checked_sub(1)?))
+    {
+        Some(resolution) => std::slice::from_ref(resolution),
+        None => resolutions,
+    };
+
+    resolutions.iter().find_map(|size| {
+        let screen = ScreenInfo {
+            frame: Rect {
+                x: 0,
+                y: 0,
+                w: size.w,
+                h: size.h,
+            },
+            screen_id: 0,
+        };
+        // Rects that would have no room left come out a pixel wide or high (see `Rect::inset`).
+        let rect = pos.to_absolute(&screen, spacing);
+        (rect.w <= 1 || rect.h <= 1).then(|| {
+            format!(
+                "The gaps (outer {}, inner {}) and margin ({}) leave no room for {:?} on a {}x{} screen",
+                spacing.gaps.outer, spacing.gaps.inner, spacing.margin, pos, size.w, size.h
+            )
+        })
+    })
+}
+
+fn is_size(length: Length) -> bool {
+    match length {
+        Length::Pixels(pixels) => pixels > 0,
+        Length::Fraction(fraction) => fraction > 0.0 && fraction <= 1.0,
+    }
+}
+
+/// Reports the owner and name patterns that were written as plain strings, and were taken as exact matches when
+/// they (probably) weren't meant to be.
+fn lint_plain_patterns(file: &Path, version: u32, raw: &Value, problems: &mut Vec<Problem>) {
+    let mut patterns: Vec<(String, &Value)> = Vec::new();
+
+    if let Some(Value::Mapping(aliases)) = raw.get("aliases") {
+        for (name, pattern) in aliases {
+            patterns.push((format!("aliases.{}", name.as_str().unwrap_or_default()), pattern));
+        }
+    }
+    let mut rule_lists = vec![("windows".to_string(), raw.get("windows"))];
+    if let Some(Value::Mapping(profiles)) = raw.get("profiles") {
+        for (name, profile) in profiles {
+            let location = format!("profiles.{}.windows", name.as_str().unwrap_or_default());
+            rule_lists.push((location, profile.get("windows")));
+        }
+    }
+    for (location, rules) in rule_lists {
+        let Some(Value::Sequence(rules)) = rules else {
+            continue;
+        };
+        for (index, rule) in rules.iter().enumerate() {
+            for field in ["owner_name", "name"] {
+                if let Some(pattern) = rule.get(field) {
+                    patterns.push((format!("{}[{}].{}", location, index, field), pattern));
+                }
+            }
+        }
+    }
+
+    for (location, pattern) in patterns {
+        let Value::String(text) = pattern else {
+            continue;
+        };
+        let message = if version >= 2 && text.starts_with('$') {
+            continue;
+        } else if version < 2 && Regex::new(text).is_err() {
+            format!(
+                "'{}' isn't a valid regular expression, so it's matched exactly. Write it as !Exact if that's what's \
+                 meant, or fix it and write it as !Regex",
+                text
+            )
+        } else if version >= 2 && text.contains(PATTERN_CHARS) {
+            format!(
+                "'{}' looks like a pattern, but plain strings are matched exactly. Write it as !Glob or !Regex if \
+                 it's a pattern, or as !Exact if it isn't",
+                text
+            )
+        } else {
+            continue;
+        };
+        problems.push(Problem::new(Severity::Warning, file, location, message));
+    }
+}
+
+/// Whether `earlier` leaves nothing for `rule` to do: it matches every window that `rule` does, and sets everything
+/// that `rule` sets.
+fn shadows(earlier: &WindowInfo, rule: &WindowInfo) -> bool {
+    covers(&earlier.owner_name, &rule.owner_name)
+        && covers(&earlier.name, &rule.name)
+        && (rule.screen_num.is_none() || earlier.screen_num.is_some())
+        && (rule.pos.is_none() || earlier.pos.is_some())
+        && (rule.scale.is_none() || earlier.scale.is_some())
+        && (rule.margin.is_none() || earlier.margin.is_some())
+}
+
+// Whether the pattern matches every value that `other` does. Only the obvious cases are recognized.
+fn covers(pattern: &MaybeRegex, other: &MaybeRegex) -> bool {
+    let matches_everything = match pattern {
+        MaybeRegex::Glob(glob, _) => !glob.is_empty() && glob.chars().all(|c| c == '*'),
+        MaybeRegex::Regex(regex, _) => regex == ".*",
+        MaybeRegex::RE(regex) => matches!(regex.as_str(), "" | ".*"),
+        _ => false,
+    };
+
+    matches_everything || pattern == other || matches!(other, MaybeRegex::Exact(value) if pattern.matches(value))
+}
+
+fn is_duplicate(earlier: &WindowInfo, rule: &WindowInfo) -> bool {
+    earlier.owner_name == rule.owner_name
+        && earlier.name == rule.name
+        && earlier.screen_num == rule.screen_num
+        && format!("{:?}", earlier.pos) == format!("{:?}", rule.pos)
+        && earlier.screen_size == rule.screen_size
+        && earlier.scale == rule.scale
+        && earlier.margin == rule.margin
+        && earlier.priority == rule.priority
+}
+
+fn contains(rules: &[WindowInfo], rule: &WindowInfo) -> bool {
+    rules.iter().any(|candidate| std::ptr::eq(candidate, rule))
+}
+
+// Where the rule is in the layout file: in the profile's rules, or in the top-level rules.
+fn locate(layout: &Layout, profile: Option<&str>, rule: &WindowInfo) -> Option<String> {
+    let index_in = |rules: &[WindowInfo]| rules.iter().position(|candidate| std::ptr::eq(candidate, rule));
+
+    if let Some(index) = index_in(&layout.windows) {
+        return Some(format!("windows[{}]", index));
+    }
+    let name = profile?;
+    let index = index_in(&layout.profiles.get(name)?.windows)?;
+    Some(format!("profiles.{}.windows[{}]", name, index))
+}
+
+fn describe(rule: &WindowInfo) -> String {
+    format!("The rule for {} / {}", rule.owner_name, rule.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint_yaml(yaml: &str) -> Vec<String> {
+        let mut layout: Layout = serde_yaml::from_str(yaml).unwrap();
+        layout.apply_version();
+        let raw: Value = serde_yaml::from_str(yaml).unwrap();
+
+        lint(Path::new("layout.yaml"), &layout, &raw)
+            .iter()
+            .map(|problem| format!("{}: {}", problem.location.as_deref().unwrap_or_default(), problem.message))
+            .collect()
+    }
+
+    #[test]
+    fn gaps_and_margins_that_leave_no_room_are_errors() {
+        let problems = lint_yaml(
+            r#"
+            version: 2
+            gaps:
+              outer: 500
+            positions:
+              quarter: !Grid {columns: 4, rows: 1, column: 1, row: 1}
+            windows:
+              - owner_name: Slack
+                name: Slack
+                pos: !Maxed
+            profiles:
+              laptop:
+                screens:
+                  resolutions: [1512x982]
+                windows:
+                  - owner_name: Music
+                    name: Music
+                    pos: $quarter
+                  - owner_name: Notes
+                    name: Notes
+                    pos: !Pos 0,0,400,300
+                    margin: 600
+            "#,
+        );
+
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert_eq!(
+            problems[0],
+            "windows[0]: The gaps (outer 500, inner 0) and margin (0) leave no room for Maxed on a 1512x982 screen"
+        );
+        assert!(problems[1].starts_with("profiles.laptop.windows[0]: The gaps (outer 500, inner 0)"), "{:?}", problems);
+    }
+
+    #[test]
+    fn bad_positions_and_screens_are_errors() {
+        let problems = lint_yaml(
+            r#"
+            version: 2
+            positions:
+              empty: !Pos 0,0,0,600
+            gaps:
+              outer: -8
+            windows:
+              - owner_name: Slack
+                name: !Glob "*"
+                screen_num: 0
+              - owner_name: Music
+                name: Music
+                pos: !Left 1.5
+              - owner_name: Code
+                name: Code
+                pos: !Grid {columns: 3, rows: 2, column: 2, row: 1, column_span: 3}
+              - owner_name: Code
+                name: Settings
+                pos: !Frac 0.5,0,0.75,-1
+              - owner_name: Code
+                name: Find
+                pos: !Center 1.5,400
+              - owner_name: Code
+                name: Search
+                pos: !Frac 0.5,0.25,0.75,0.5
+                margin: -4
+              - owner_name: Code
+                name: Terminal
+                pos: !Grid {columns: 3, rows: 2, column: 4294967295, row: 1, column_span: 2}
+              - owner_name: Code
+                name: Outline
+                pos: !Left 0
+            "#,
+        );
+
+        assert_eq!(
+            problems,
+            [
+                "positions.empty: The rect 0,0,0,600 is malformed: its width and height have to be more than 0",
+                "gaps: The gaps (outer -8, inner 0) can't be negative",
+                "windows[0]: screen_num 0 doesn't exist: screens are numbered from 1 (the left-most)",
+                "windows[1]: Left(1.5) has to be more than 0 and at most 1: it's a fraction of the screen",
+                "windows[2]: Column 2 (spanning 3) doesn't fit in a grid of 3 columns",
+                "windows[3]: The fractional rect 0.5,0,0.75,-1 is malformed: its values are fractions of the screen, \
+                 between 0 and 1, and its width and height have to be more than 0",
+                "windows[4]: The size 1.5,400 is malformed: the width and height have to be more than 0 pixels, or \
+                 fractions of the screen between 0 and 1",
+                "windows[5]: The margin -4 can't be negative",
+                "windows[5]: The fractional rect 0.5,0.25,0.75,0.5 goes past the edge of the screen: x + w and y + h \
+                 can't be more than 1",
+                "windows[6]: Column 4294967295 (spanning 2) doesn't fit in a grid of 3 columns",
+                "windows[7]: Left(0.0) has to be more than 0 and at most 1: it's a fraction of the screen",
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_strings_that_look_like_patterns_are_reported() {
+        let version_1 = lint_yaml(
+            r#"
+            windows:
+              - owner_name: Code
+                name: "[unclosed"
+                pos: !Maxed
+            "#,
+        );
+        let version_2 = lint_yaml(
+            r#"
+            version: 2
+            aliases:
+              browsers: Firefox|Safari
+            windows:
+              - owner_name: Code
+                name: "* - *"
+                pos: !Maxed
+              - owner_name: Microsoft Outlook
+                name: Inbox (3)
+                pos: !Maxed
+            "#,
+        );
+
+        assert_eq!(version_1.len(), 1);
+        assert!(version_1[0].starts_with("windows[0].name: '[unclosed' isn't a valid regular expression"));
+        assert_eq!(version_2.len(), 2);
+        assert!(version_2[0].starts_with("aliases.browsers: 'Firefox|Safari' looks like a pattern"));
+        assert!(version_2[1].starts_with("windows[0].name: '* - *' looks like a pattern"));
+    }
+
+    #[test]
+    fn shadowed_and_duplicate_rules_are_reported() {
+        let problems = lint_yaml(
+            r#"
+            version: 2
+            windows:
+              - owner_name: Slack
+                name: !Glob "*"
+                screen_num: 2
+                pos: !Maxed
+                priority: 1
+              - owner_name: Slack
+                name: Huddle
+                pos: !Pos 0,0,400,300
+              - owner_name: Music
+                name: Music
+                screen_num: 1
+              - owner_name: Music
+                name: Music
+                screen_num: 1
+              - owner_name: Music
+                name: !Glob "*"
+                pos: !Maxed
+            profiles:
+              docked:
+                windows:
+                  - owner_name: Music
+                    name: Music
+                    screen_num: 3
+            "#,
+        );
+
+        assert_eq!(
+            problems,
+            [
+                "windows[1]: The rule for Slack / Huddle never takes effect: windows[0] is tried first, matches every \
+                 window it does, and sets everything it sets",
+                "windows[3]: The rule for Music / Music duplicates windows[2]",
+            ]
+        );
+    }
+
+    #[test]
+    fn load_errors_keep_their_location() {
+        let error = anyhow::Error::from(LayoutError::Parse {
+            path: "layout.yaml".into(),
+            line: 4,
+            column: 10,
+            message: "Invalid rect '1,2,3', expected x,y,w,h".into(),
+        });
+
+        assert_eq!(
+            Problem::from_error(&error).to_string(),
+            "layout.yaml:4:10: error: Invalid rect '1,2,3', expected x,y,w,h"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use std::thread::sleep;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::LayoutError;
+use crate::layout_types::{MatchingWindowInfo, Rect, ScreenInfo};
+
+#[cfg(test)]
+pub mod fake;
+#[cfg(target_os = "linux")]
+pub mod hyprland;
+#[cfg(target_os = "macos")]
+pub mod macos;
+pub mod recording;
+#[cfg(target_os = "linux")]
+pub mod sway;
+#[cfg(target_os = "linux")]
+pub mod x11;
+
+/// A desktop window, as reported by a `WindowSystem`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DesktopWindow {
+    pub owner_name: String,
+    pub name: String,
+    pub process_id: i32,
+    // Wide enough for Hyprland, whose window "ids" are addresses.
+    pub window_id: u64,
+    // Absolute bounds, i.e. not relative to the containing screen.
+    pub bounds: Rect,
+}
+
+/// The platform-specific part of Layout: enumerating screens and windows, and moving windows around.
+/// Everything else (matching windows against the layout file, deciding what needs to move) is done
+/// in terms of this trait, so that it doesn't care what it's running on.
+pub trait WindowSystem {
+    /// Returns the current screens, in any order.
+    fn get_screens(&self) -> anyhow::Result<Vec<ScreenInfo>>;
+
+    /// Returns the current desktop windows, in any order.
+    fn get_windows(&self) -> anyhow::Result<Vec<DesktopWindow>>;
+
+    /// Moves the specified window to the desired (absolute) location and size.
+    fn move_window(&self, window: &MatchingWindowInfo, bounds: &Rect) -> anyhow::Result<()>;
+
+    /// Called after a round of moves, to give the windows time to settle into their new positions.
+    fn wait_for_moves(&self) {
+        sleep(Duration::from_millis(500));
+    }
+}
+
+/// Returns the screen id for the output with the specified (connector) name, e.g. "DP-1". The ids that X11, Sway and
+/// Hyprland give their outputs depend on the order they were connected in, and can change from one session to the
+/// next, but the names don't, so profiles can rely on these.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub fn output_screen_id(name: &str) -> u32 {
+    // FNV-1a, which (unlike std's hasher) is guaranteed to give the same result in every build.
+    name.bytes()
+        .fold(0x811c_9dc5, |hash, byte| (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193))
+}
+
+/// Returns the window system for the platform we're running on.
+pub fn native_window_system() -> anyhow::Result<Box<dyn WindowSystem>> {
+    #[cfg(target_os = "macos")]
+    return Ok(Box::new(macos::MacWindowSystem));
+
+    #[cfg(target_os = "linux")]
+    if let Some(window_system) = hyprland::HyprlandWindowSystem::from_env() {
+        return Ok(Box::new(window_system));
+    }
+
+    // Sway and i3 are checked before X11: Sway runs XWayland (so DISPLAY is set), and although i3 is an X11 window
+    // manager, its IPC is the only reliable way to float windows so they can be moved.
+    #[cfg(target_os = "linux")]
+    if let Some(window_system) = sway::SwayWindowSystem::from_env() {
+        return Ok(Box::new(window_system));
+    }
+
+    #[cfg(target_os = "linux")]
+    if std::env::var_os("DISPLAY").is_some() {
+        return Ok(Box::new(x11::X11WindowSystem::connect()?));
+    }
+
+    #[allow(unreachable_code)]
+    Err(LayoutError::Backend("No window system is supported on this platform.".into()).into())
+}
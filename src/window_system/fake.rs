@@ -0,0 +1,90 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::anyhow;
+use serde::Deserialize;
+
+use crate::layout_types::{MatchingWindowInfo, Rect, ScreenInfo};
+use crate::window_system::{DesktopWindow, WindowSystem};
+
+/// An in-memory desktop, for running the layout logic without a real window system.
+/// Every move request is recorded (and applied to the in-memory window) so that tests can check exactly
+/// which windows were moved, and where.
+pub struct FakeWindowSystem {
+    screens: Vec<ScreenInfo>,
+    windows: RefCell<Vec<DesktopWindow>>,
+    moves: RefCell<Vec<FakeMove>>,
+}
+
+/// The initial state of a `FakeWindowSystem`.
+#[derive(Debug, Default, Deserialize)]
+pub struct FakeDesktop {
+    pub screens: Vec<ScreenInfo>,
+    pub windows: Vec<DesktopWindow>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FakeMove {
+    pub window_id: u64,
+    pub bounds: Rect,
+}
+
+impl FakeWindowSystem {
+    pub fn new(desktop: FakeDesktop) -> Self {
+        FakeWindowSystem {
+            screens: desktop.screens,
+            windows: RefCell::new(desktop.windows),
+            moves: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Loads the initial state from a YAML (or JSON, which YAML is a superset of) fixture file.
+    pub fn from_fixture(path: &Path) -> anyhow::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(Self::new(serde_yaml::from_reader(reader)?))
+    }
+
+    /// Returns the move requests received so far, in order.
+    pub fn moves(&self) -> Vec<FakeMove> {
+        self.moves.borrow().clone()
+    }
+
+    /// Returns the current bounds of the specified window.
+    pub fn bounds_of(&self, window_id: u64) -> Option<Rect> {
+        self.windows
+            .borrow()
+            .iter()
+            .find(|window| window.window_id == window_id)
+            .map(|window| window.bounds.clone())
+    }
+}
+
+impl WindowSystem for FakeWindowSystem {
+    fn get_screens(&self) -> anyhow::Result<Vec<ScreenInfo>> {
+        Ok(self.screens.clone())
+    }
+
+    fn get_windows(&self) -> anyhow::Result<Vec<DesktopWindow>> {
+        Ok(self.windows.borrow().clone())
+    }
+
+    fn move_window(&self, window: &MatchingWindowInfo, bounds: &Rect) -> anyhow::Result<()> {
+        self.moves.borrow_mut().push(FakeMove {
+            window_id: window.window_id,
+            bounds: bounds.clone(),
+        });
+
+        let mut windows = self.windows.borrow_mut();
+        let desktop_window = windows
+            .iter_mut()
+            .find(|desktop_window| desktop_window.window_id == window.window_id)
+            .ok_or_else(|| anyhow!("Window not found"))?;
+        desktop_window.bounds = bounds.clone();
+
+        Ok(())
+    }
+
+    fn wait_for_moves(&self) {}
+}
@@ -0,0 +1,333 @@
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use log::{error, trace};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::error::LayoutError;
+use crate::layout_types::{MatchingWindowInfo, Rect, ScreenInfo};
+use crate::window_system::{output_screen_id, DesktopWindow, WindowSystem};
+
+/// The Hyprland window system, which sends requests to Hyprland's `.socket.sock` socket.
+/// <br>Like Sway, tiled windows are made floating before they're moved.
+pub struct HyprlandWindowSystem {
+    socket_path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct Monitor {
+    name: String,
+    x: i32,
+    y: i32,
+    // The width and height are in physical pixels, but window positions are in logical ones.
+    width: i32,
+    height: i32,
+    scale: f32,
+    // 1, 3, 5 and 7 are rotated by 90 or 270 degrees.
+    transform: u32,
+    #[serde(default)]
+    disabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Workspace {
+    id: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Client {
+    address: String,
+    mapped: bool,
+    hidden: bool,
+    at: (i32, i32),
+    size: (i32, i32),
+    workspace: Workspace,
+    floating: bool,
+    class: String,
+    title: String,
+    pid: i32,
+}
+
+impl HyprlandWindowSystem {
+    pub fn new(socket_path: PathBuf) -> Self {
+        HyprlandWindowSystem { socket_path }
+    }
+
+    /// Returns the window system for the running Hyprland instance, if there is one.
+    pub fn from_env() -> Option<Self> {
+        let signature = std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE")?;
+
+        // Hyprland moved its sockets from /tmp to $XDG_RUNTIME_DIR in v0.40.
+        let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(|runtime_dir| PathBuf::from(runtime_dir).join("hypr").join(&signature))
+            .filter(|runtime_dir| runtime_dir.exists());
+        let instance_dir = runtime_dir.unwrap_or_else(|| PathBuf::from("/tmp/hypr").join(&signature));
+
+        Some(Self::new(instance_dir.join(".socket.sock")))
+    }
+
+    /// Sends a single request. Hyprland replies and then closes the connection.
+    fn request(&self, request: &str) -> anyhow::Result<String> {
+        let mut stream = UnixStream::connect(&self.socket_path)?;
+        stream.write_all(request.as_bytes())?;
+
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply)?;
+        Ok(reply)
+    }
+
+    fn request_json<T: DeserializeOwned>(&self, request: &str) -> anyhow::Result<T> {
+        Ok(serde_json::from_str(&self.request(&format!("j/{}", request))?)?)
+    }
+
+    fn dispatch(&self, dispatcher: &str) -> anyhow::Result<()> {
+        trace!("Dispatching: {}", dispatcher);
+
+        let reply = self.request(&format!("dispatch {}", dispatcher))?;
+        if reply.trim() != "ok" {
+            return Err(anyhow!("'{}' failed: {}", dispatcher, reply.trim()));
+        }
+        Ok(())
+    }
+}
+
+impl WindowSystem for HyprlandWindowSystem {
+    fn get_screens(&self) -> anyhow::Result<Vec<ScreenInfo>> {
+        let monitors: Vec<Monitor> = self
+            .request_json("monitors")
+            .map_err(|e| LayoutError::Backend(format!("Failed to retrieve monitors: {}", e)))?;
+
+        Ok(monitors
+            .into_iter()
+            .filter(|monitor| !monitor.disabled)
+            .map(|monitor| {
+                let (width, height) = match monitor.transform % 2 {
+                    0 => (monitor.width, monitor.height),
+                    _ => (monitor.height, monitor.width),
+                };
+                ScreenInfo {
+                    frame: Rect {
+                        x: monitor.x,
+                        y: monitor.y,
+                        w: (width as f32 / monitor.scale).round() as i32,
+                        h: (height as f32 / monitor.scale).round() as i32,
+                    },
+                    screen_id: output_screen_id(&monitor.name),
+                }
+            })
+            .collect())
+    }
+
+    fn get_windows(&self) -> anyhow::Result<Vec<DesktopWindow>> {
+        let clients: Vec<Client> = self
+            .request_json("clients")
+            .map_err(|e| LayoutError::Backend(format!("Failed to retrieve clients: {}", e)))?;
+
+        Ok(clients
+            .into_iter()
+            // Special workspaces (e.g. the scratchpad) have negative ids.
+            .filter(|client| client.mapped && !client.hidden && client.workspace.id > 0)
+            .filter_map(|client| {
+                let window_id = match parse_address(&client.address) {
+                    Ok(window_id) => window_id,
+                    Err(e) => {
+                        error!("{}", e);
+                        return None;
+                    }
+                };
+                Some(DesktopWindow {
+                    owner_name: client.class,
+                    name: client.title,
+                    process_id: client.pid,
+                    window_id,
+                    bounds: Rect {
+                        x: client.at.0,
+                        y: client.at.1,
+                        w: client.size.0,
+                        h: client.size.1,
+                    },
+                })
+            })
+            .collect())
+    }
+
+    fn move_window(&self, window: &MatchingWindowInfo, bounds: &Rect) -> anyhow::Result<()> {
+        let address = format!("address:0x{:x}", window.window_id);
+
+        let clients: Vec<Client> = self.request_json("clients")?;
+        let client = clients
+            .iter()
+            .find(|client| parse_address(&client.address).ok() == Some(window.window_id))
+            .ok_or_else(|| anyhow!("Window not found"))?;
+
+        if !client.floating {
+            self.dispatch(&format!("togglefloating {}", address))?;
+        }
+        self.dispatch(&format!("movewindowpixel exact {} {},{}", bounds.x, bounds.y, address))?;
+        self.dispatch(&format!("resizewindowpixel exact {} {},{}", bounds.w, bounds.h, address))
+    }
+}
+
+/// Hyprland identifies windows by address, e.g. "0x55d5c3c6a1e0".
+fn parse_address(address: &str) -> anyhow::Result<u64> {
+    u64::from_str_radix(address.trim_start_matches("0x"), 16)
+        .map_err(|e| anyhow!("Invalid window address '{}': {}", address, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::path::Path;
+    use std::thread;
+
+    use super::*;
+
+    /// A stand-in for Hyprland that serves canned JSON, and returns the requests it received.
+    fn stub_server(socket_path: &Path, requests: usize) -> thread::JoinHandle<Vec<String>> {
+        let listener = UnixListener::bind(socket_path).unwrap();
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/hyprland");
+
+        thread::spawn(move || {
+            let mut received = Vec::new();
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut request = [0u8; 1024];
+                let length = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..length]).into_owned();
+
+                let reply = match request.as_str() {
+                    "j/monitors" => fs::read_to_string(fixtures.join("monitors.json")).unwrap(),
+                    "j/clients" => fs::read_to_string(fixtures.join("clients.json")).unwrap(),
+                    _ => "ok".to_string(),
+                };
+                stream.write_all(reply.as_bytes()).unwrap();
+
+                received.push(request);
+            }
+            received
+        })
+    }
+
+    #[test]
+    fn enumerates_monitors_and_clients() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join(".socket.sock");
+        let server = stub_server(&socket_path, 2);
+        let window_system = HyprlandWindowSystem::new(socket_path);
+
+        let screens = window_system.get_screens().unwrap();
+        let windows = window_system.get_windows().unwrap();
+        server.join().unwrap();
+
+        // The laptop panel is scaled by 2, and the portrait monitor is rotated.
+        assert_eq!(
+            screens,
+            vec![
+                ScreenInfo {
+                    frame: Rect {
+                        x: 0,
+                        y: 0,
+                        w: 1440,
+                        h: 900
+                    },
+                    screen_id: output_screen_id("eDP-1"),
+                },
+                ScreenInfo {
+                    frame: Rect {
+                        x: 1440,
+                        y: 0,
+                        w: 1440,
+                        h: 2560
+                    },
+                    screen_id: output_screen_id("DP-2"),
+                },
+            ]
+        );
+        // The scratchpad window is skipped.
+        assert_eq!(
+            windows,
+            vec![
+                DesktopWindow {
+                    owner_name: "kitty".into(),
+                    name: "~/src/layout-rust".into(),
+                    process_id: 1234,
+                    window_id: 0x55d5c3c6a1e0,
+                    bounds: Rect {
+                        x: 5,
+                        y: 5,
+                        w: 1430,
+                        h: 890
+                    },
+                },
+                DesktopWindow {
+                    owner_name: "firefox".into(),
+                    name: "Mozilla Firefox".into(),
+                    process_id: 2345,
+                    window_id: 0x55d5c3d81f30,
+                    bounds: Rect {
+                        x: 1540,
+                        y: 200,
+                        w: 1200,
+                        h: 900
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn floats_tiled_windows_before_moving_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join(".socket.sock");
+        let server = stub_server(&socket_path, 4);
+        let window_system = HyprlandWindowSystem::new(socket_path);
+        let window = MatchingWindowInfo {
+            window_id: 0x55d5c3c6a1e0,
+            ..MatchingWindowInfo::default()
+        };
+        let bounds = Rect {
+            x: 1440,
+            y: 0,
+            w: 1440,
+            h: 1280,
+        };
+
+        window_system.move_window(&window, &bounds).unwrap();
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                "j/clients",
+                "dispatch togglefloating address:0x55d5c3c6a1e0",
+                "dispatch movewindowpixel exact 1440 0,address:0x55d5c3c6a1e0",
+                "dispatch resizewindowpixel exact 1440 1280,address:0x55d5c3c6a1e0",
+            ]
+        );
+    }
+
+    #[test]
+    fn floating_windows_are_moved_as_is() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join(".socket.sock");
+        let server = stub_server(&socket_path, 3);
+        let window_system = HyprlandWindowSystem::new(socket_path);
+        let window = MatchingWindowInfo {
+            window_id: 0x55d5c3d81f30,
+            ..MatchingWindowInfo::default()
+        };
+        let bounds = Rect {
+            x: 0,
+            y: 0,
+            w: 1440,
+            h: 900,
+        };
+
+        window_system.move_window(&window, &bounds).unwrap();
+
+        assert_eq!(server.join().unwrap()[1], "dispatch movewindowpixel exact 0 0,address:0x55d5c3d81f30");
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashSet;
+use std::ffi::c_void;
+
+use accessibility_sys::{
+    kAXErrorSuccess, kAXPositionAttribute, kAXSizeAttribute, kAXValueTypeCGPoint, kAXValueTypeCGSize,
+    kAXWindowsAttribute, AXError, AXUIElementCopyAttributeValue, AXUIElementCreateApplication, AXUIElementRef,
+    AXUIElementSetAttributeValue, AXValueCreate,
+};
+use anyhow::anyhow;
+use cocoa::appkit::NSScreen;
+use cocoa_foundation::base::{id, nil};
+use cocoa_foundation::foundation::{NSArray, NSFastEnumeration, NSString, NSUInteger};
+use core_foundation::base::*;
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::string::*;
+use core_foundation_sys::dictionary::CFDictionaryRef;
+use core_graphics::display;
+use core_graphics::display::{CGDisplay, CGWindowID};
+use core_graphics_types::base::CGFloat;
+use core_graphics_types::geometry::{CGPoint, CGRect, CGSize};
+use log::trace;
+
+use crate::dict_utils::{get_dict_from_dict, get_num_from_dict, get_string_from_dict};
+use crate::error::LayoutError;
+use crate::idref::IdRef;
+use crate::layout_types::{MatchingWindowInfo, Rect, ScreenInfo};
+use crate::window_system::{DesktopWindow, WindowSystem};
+
+extern "C" {
+    pub fn _AXUIElementGetWindow(element: AXUIElementRef, out: *mut CGWindowID) -> AXError;
+}
+
+/// The MacOS window system: windows are enumerated with the CGWindowList API and moved with the
+/// Accessibility API.
+pub struct MacWindowSystem;
+
+impl WindowSystem for MacWindowSystem {
+    fn get_screens(&self) -> anyhow::Result<Vec<ScreenInfo>> {
+        get_screens()
+    }
+
+    fn get_windows(&self) -> anyhow::Result<Vec<DesktopWindow>> {
+        get_windows()
+    }
+
+    fn move_window(&self, window: &MatchingWindowInfo, bounds: &Rect) -> anyhow::Result<()> {
+        move_window(window, bounds)
+    }
+}
+
+// What to do when the screens or windows can't be enumerated.
+const PERMISSION_ADVICE: &str = "Please add layout to the 'Screen & System Audio Recording' apps\n\
+                                 in System Preferences -> Privacy & Security";
+
+/// Returns a list of the current screens.
+fn get_screens() -> anyhow::Result<Vec<ScreenInfo>> {
+    let mut screens = vec![];
+
+    unsafe {
+        let ns_screens = NSScreen::screens(nil);
+        let primary_screen = NSScreen::mainScreen(nil);
+        let primary_frame = primary_screen.frame();
+        let screen_num_key = IdRef::new(NSString::alloc(nil).init_str("NSScreenNumber"));
+
+        for screen in ns_screens.iter() {
+            let frame = screen.frame();
+
+            // I'm going to convert all NSScreen coords (which are bizarrely reversed in the Y axis) to the
+            // same orientation as window coords.
+            let fixed_y = primary_frame.size.height - frame.size.height - frame.origin.y;
+
+            let device_desc = screen.deviceDescription();
+            let screen_id: id = msg_send![device_desc, objectForKey:*screen_num_key];
+            let screen_id: NSUInteger = msg_send![screen_id, unsignedIntegerValue];
+            screens.push(ScreenInfo {
+                screen_id: screen_id as u32,
+                frame: Rect {
+                    x: frame.origin.x as i32,
+                    y: fixed_y as i32,
+                    w: frame.size.width as i32,
+                    h: frame.size.height as i32,
+                },
+            });
+        }
+    }
+
+    if screens.is_empty() {
+        return Err(LayoutError::Permission(format!("Unable to enumerate screens.\n{}", PERMISSION_ADVICE)).into());
+    }
+
+    Ok(screens)
+}
+
+/// Returns a list of window owners that we wish to ignore.
+fn owners_to_ignore() -> HashSet<String> {
+    HashSet::from(["Control Center".into(), "Dock".into(), "Window Server".into()])
+}
+
+/// Returns the current desktop windows.
+fn get_windows() -> anyhow::Result<Vec<DesktopWindow>> {
+    let mut windows = Vec::new();
+    let owners_to_ignore = owners_to_ignore();
+
+    let cg_window_infos = CGDisplay::window_list_info(
+        display::kCGWindowListExcludeDesktopElements | display::kCGWindowListOptionOnScreenOnly,
+        None,
+    );
+
+    let cg_window_infos =
+        cg_window_infos.ok_or_else(|| LayoutError::Backend("Failed to retrieve list of windows.".into()))?;
+
+    for cg_window_info in cg_window_infos.iter() {
+        // window_info is a dictionary. Need to recast...
+        let window_dict: CFDictionary<*const c_void, *const c_void> =
+            unsafe { CFDictionary::wrap_under_get_rule(*cg_window_info as CFDictionaryRef) };
+
+        let owner_name = get_string_from_dict(&window_dict, "kCGWindowOwnerName");
+        let name = get_string_from_dict(&window_dict, "kCGWindowName");
+
+        // Without Screen Recording permission every window's name is empty, so these have to be dropped before the
+        // emptiness check below.
+        if owner_name.is_empty() || name.is_empty() || owners_to_ignore.contains(&owner_name) {
+            continue;
+        }
+
+        let bounds = match get_dict_from_dict(&window_dict, "kCGWindowBounds")
+            .and_then(|bounds| CGRect::from_dict_representation(&bounds))
+        {
+            Some(value) => value,
+            None => continue,
+        };
+
+        windows.push(DesktopWindow {
+            owner_name,
+            name,
+            process_id: get_num_from_dict(&window_dict, "kCGWindowOwnerPID"),
+            window_id: get_num_from_dict::<CGWindowID>(&window_dict, "kCGWindowNumber").into(),
+            bounds: bounds.into(),
+        });
+    }
+
+    if windows.is_empty() {
+        return Err(LayoutError::Permission(format!("Unable to enumerate windows.\n{}", PERMISSION_ADVICE)).into());
+    }
+
+    Ok(windows)
+}
+
+/// Moves the specified window to the desired location.
+fn move_window(matching_window: &MatchingWindowInfo, desired_absolute_bounds: &Rect) -> anyhow::Result<()> {
+    let axwindow = get_axwindow(matching_window.process_id, matching_window.window_id as CGWindowID)?;
+    trace!("Found axwindow for {:?}/{:?}", matching_window.process_id, matching_window.window_id);
+
+    let mut cg_pos: CGPoint = desired_absolute_bounds.origin();
+    let position = unsafe {
+        AXValueCreate(
+            kAXValueTypeCGPoint,
+            // What a masterpiece of ugliness:
+            &mut cg_pos as *mut _ as *mut c_void,
+        )
+    };
+    let result = unsafe {
+        AXUIElementSetAttributeValue(axwindow, CFString::new(kAXPositionAttribute).as_concrete_TypeRef(), position as _)
+    };
+    if result != kAXErrorSuccess {
+        return Err(anyhow!("AXUIElementSetAttributeValue(kAXPositionAttribute) failed: {:?}", result));
+    }
+
+    let mut cg_size: CGSize = desired_absolute_bounds.size();
+    let size = unsafe { AXValueCreate(kAXValueTypeCGSize, &mut cg_size as *mut _ as *mut c_void) };
+
+    let result = unsafe {
+        AXUIElementSetAttributeValue(axwindow, CFString::new(kAXSizeAttribute).as_concrete_TypeRef(), size as _)
+    };
+    if result != kAXErrorSuccess {
+        return Err(anyhow!("AXUIElementSetAttributeValue(kAXSizeAttribute) failed: {:?}", result));
+    }
+
+    Ok(())
+}
+
+/// Given an Owner ID and Window ID from the `CGWindowList` API, returns the corresponding `AXUIElementRef` to
+/// use with the Accessibility API.
+/// <br>(We need this because, annoyingly, we enumerate desktop windows using the CGWindowList API,
+/// and yet we have to use an entirely different API -- the Accessibility API -- to actually move the
+/// windows).
+fn get_axwindow(owner_id: i32, window_id: CGWindowID) -> anyhow::Result<AXUIElementRef> {
+    let ax_application = unsafe { AXUIElementCreateApplication(owner_id) };
+    let mut windows_ref: CFTypeRef = std::ptr::null();
+
+    if ax_application.is_null() {
+        return Err(anyhow!("Failed to get application handle."));
+    }
+
+    if unsafe {
+        AXUIElementCopyAttributeValue(
+            ax_application,
+            CFString::new(kAXWindowsAttribute).as_concrete_TypeRef(),
+            &mut windows_ref as *mut CFTypeRef,
+        )
+    } != kAXErrorSuccess
+    {
+        unsafe {
+            CFRelease(ax_application.cast());
+        }
+
+        return Err(anyhow!("Failed to get application attribute."));
+    }
+
+    if windows_ref.is_null() {
+        unsafe {
+            CFRelease(windows_ref.cast());
+            CFRelease(ax_application.cast());
+        }
+
+        return Err(anyhow!("Failed to get application attribute."));
+    }
+
+    let windows_ref = windows_ref as id;
+
+    let count = unsafe { NSArray::count(windows_ref) };
+
+    for i in 0..count {
+        let ax_window = unsafe { NSArray::objectAtIndex(windows_ref, i) };
+
+        let ax_window_id = {
+            let mut id: CGWindowID = 0;
+            if unsafe { _AXUIElementGetWindow(ax_window as AXUIElementRef, &mut id) } != kAXErrorSuccess {
+                continue;
+            }
+            id
+        };
+
+        if ax_window_id == window_id {
+            unsafe {
+                CFRetain(ax_window.cast());
+                CFRelease(windows_ref.cast());
+                CFRelease(ax_application.cast());
+            }
+
+            // return Ok(unsafe { AXUIElement::wrap_under_create_rule(ax_window as AXUIElementRef) });
+            return Ok(ax_window as AXUIElementRef);
+        }
+    }
+
+    unsafe {
+        CFRelease(windows_ref.cast());
+        CFRelease(ax_application.cast());
+    }
+
+    Err(anyhow!("Window not found"))
+}
+
+impl Rect {
+    pub fn origin(&self) -> CGPoint {
+        CGPoint {
+            x: self.x as CGFloat,
+            y: self.y as CGFloat,
+        }
+    }
+
+    pub fn size(&self) -> CGSize {
+        CGSize {
+            width: self.w as CGFloat,
+            height: self.h as CGFloat,
+        }
+    }
+}
+
+impl From<Rect> for CGRect {
+    fn from(value: Rect) -> Self {
+        CGRect {
+            origin: value.origin(),
+            size: value.size(),
+        }
+    }
+}
+
+impl From<CGRect> for Rect {
+    fn from(value: CGRect) -> Self {
+        Rect {
+            x: value.origin.x as i32,
+            y: value.origin.y as i32,
+            w: value.size.width as i32,
+            h: value.size.height as i32,
+        }
+    }
+}
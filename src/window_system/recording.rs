@@ -0,0 +1,293 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{self, LayoutError};
+use crate::layout_types::{MatchingWindowInfo, Rect, ScreenInfo};
+use crate::window_system::{DesktopWindow, WindowSystem};
+
+/// Everything a `WindowSystem` was asked, and what it answered, in order.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub events: Vec<Event>,
+}
+
+// Failed calls are recorded too, so that a replay fails the same way.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Event {
+    GetScreens {
+        result: Result<Vec<ScreenInfo>, RecordedError>,
+    },
+    GetWindows {
+        result: Result<Vec<DesktopWindow>, RecordedError>,
+    },
+    MoveWindow {
+        window: MatchingWindowInfo,
+        bounds: Rect,
+        result: Result<(), RecordedError>,
+    },
+}
+
+/// An error, as it's recorded: its message, and its exit code (see `LayoutError::exit_code`), so that the replayed
+/// error is the same kind of `LayoutError`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RecordedError {
+    pub message: String,
+    pub exit_code: i32,
+}
+
+impl From<&anyhow::Error> for RecordedError {
+    fn from(e: &anyhow::Error) -> Self {
+        RecordedError {
+            message: e.to_string(),
+            exit_code: error::exit_code(e),
+        }
+    }
+}
+
+impl From<RecordedError> for anyhow::Error {
+    fn from(e: RecordedError) -> Self {
+        match LayoutError::rebuild(e.exit_code, e.message.clone()) {
+            Some(layout_error) => layout_error.into(),
+            None => anyhow!(e.message),
+        }
+    }
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).map_err(|source| LayoutError::read(path, source))?;
+
+        Ok(serde_yaml::from_reader(BufReader::new(file)).map_err(|e| LayoutError::parse(path, &e))?)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let text = serde_yaml::to_string(self)?;
+
+        Ok(std::fs::write(path, text).map_err(|source| LayoutError::write(path, source))?)
+    }
+}
+
+/// Wraps another window system, and records every call made to it.
+/// <br>The recording is written out after every call rather than at the end, so that there's something to look
+/// at even when Layout crashes part way through.
+pub struct RecordingWindowSystem {
+    inner: Box<dyn WindowSystem>,
+    path: PathBuf,
+    recording: RefCell<Recording>,
+}
+
+impl RecordingWindowSystem {
+    pub fn new(inner: Box<dyn WindowSystem>, path: PathBuf) -> Self {
+        RecordingWindowSystem {
+            inner,
+            path,
+            recording: RefCell::new(Recording::default()),
+        }
+    }
+
+    fn record(&self, event: Event) {
+        let mut recording = self.recording.borrow_mut();
+        recording.events.push(event);
+
+        if let Err(e) = recording.save(&self.path) {
+            error!("Failed to save the recording: {}", e);
+        }
+    }
+}
+
+impl WindowSystem for RecordingWindowSystem {
+    fn get_screens(&self) -> anyhow::Result<Vec<ScreenInfo>> {
+        let result = self.inner.get_screens();
+        self.record(Event::GetScreens {
+            result: recorded(&result),
+        });
+        result
+    }
+
+    fn get_windows(&self) -> anyhow::Result<Vec<DesktopWindow>> {
+        let result = self.inner.get_windows();
+        self.record(Event::GetWindows {
+            result: recorded(&result),
+        });
+        result
+    }
+
+    fn move_window(&self, window: &MatchingWindowInfo, bounds: &Rect) -> anyhow::Result<()> {
+        let result = self.inner.move_window(window, bounds);
+        self.record(Event::MoveWindow {
+            window: window.clone(),
+            bounds: bounds.clone(),
+            result: recorded(&result),
+        });
+        result
+    }
+
+    fn wait_for_moves(&self) {
+        self.inner.wait_for_moves();
+    }
+}
+
+// A call's result, as it's recorded.
+fn recorded<T: Clone>(result: &anyhow::Result<T>) -> Result<T, RecordedError> {
+    match result {
+        Ok(value) => Ok(value.clone()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// A recorded move_window call: the window, the bounds it was moved to, and the result.
+type RecordedMove = (MatchingWindowInfo, Rect, Result<(), RecordedError>);
+
+/// Plays back a `Recording` in place of a real window system.
+/// <br>Screens and windows are handed out in the order they were recorded (and once they run out, the last ones
+/// are repeated). Moves return the recorded result, as long as they match what was recorded; if the layout file
+/// has changed since the recording was made, they won't, and a warning is logged.
+pub struct ReplayWindowSystem {
+    screens: RefCell<VecDeque<Result<Vec<ScreenInfo>, RecordedError>>>,
+    windows: RefCell<VecDeque<Result<Vec<DesktopWindow>, RecordedError>>>,
+    moves: RefCell<VecDeque<RecordedMove>>,
+}
+
+impl ReplayWindowSystem {
+    pub fn new(recording: Recording) -> Self {
+        let mut screens = VecDeque::new();
+        let mut windows = VecDeque::new();
+        let mut moves = VecDeque::new();
+
+        for event in recording.events {
+            match event {
+                Event::GetScreens { result } => screens.push_back(result),
+                Event::GetWindows { result } => windows.push_back(result),
+                Event::MoveWindow { window, bounds, result } => moves.push_back((window, bounds, result)),
+            }
+        }
+
+        ReplayWindowSystem {
+            screens: RefCell::new(screens),
+            windows: RefCell::new(windows),
+            moves: RefCell::new(moves),
+        }
+    }
+}
+
+/// Returns the next recorded result (or the last one if there are no more, or nothing if none were recorded).
+fn next_or_last<T: Clone + Default>(results: &RefCell<VecDeque<Result<T, RecordedError>>>) -> anyhow::Result<T> {
+    let mut results = results.borrow_mut();
+    let result = if results.len() > 1 { results.pop_front() } else { results.front().cloned() };
+    Ok(result.unwrap_or_else(|| Ok(T::default()))?)
+}
+
+impl WindowSystem for ReplayWindowSystem {
+    fn get_screens(&self) -> anyhow::Result<Vec<ScreenInfo>> {
+        next_or_last(&self.screens)
+    }
+
+    fn get_windows(&self) -> anyhow::Result<Vec<DesktopWindow>> {
+        next_or_last(&self.windows)
+    }
+
+    fn move_window(&self, window: &MatchingWindowInfo, bounds: &Rect) -> anyhow::Result<()> {
+        match self.moves.borrow_mut().pop_front() {
+            Some((recorded_window, recorded_bounds, result))
+                if recorded_window.window_id == window.window_id && recorded_bounds == *bounds =>
+            {
+                Ok(result?)
+            }
+            Some((recorded_window, recorded_bounds, _)) => {
+                warn!(
+                    "Replay diverged: moving window {} to {:?}, but the recording moved window {} to {:?}",
+                    window.window_id, bounds, recorded_window.window_id, recorded_bounds
+                );
+                Ok(())
+            }
+            None => {
+                warn!(
+                    "Replay diverged: moving window {} to {:?}, but the recording has no more moves",
+                    window.window_id, bounds
+                );
+                Ok(())
+            }
+        }
+    }
+
+    fn wait_for_moves(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::window_system::fake::FakeWindowSystem;
+    use crate::{load_layout_file, restore_layout};
+
+    #[test]
+    fn replays_a_recorded_restore() {
+        let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let desired_layout = load_layout_file(format!("{}/sample-layout.yaml", manifest_dir.display())).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.yaml");
+
+        let fake = FakeWindowSystem::from_fixture(&manifest_dir.join("tests/fixtures/three-screens.yaml")).unwrap();
+        let recorder = RecordingWindowSystem::new(Box::new(fake), path.clone());
+        restore_layout(&recorder, &desired_layout, None, false).unwrap();
+        let recording = Recording::load(&path).unwrap();
+
+        // The first pass moves 4 windows, and the second finds everything where it should be.
+        let moves: Vec<_> = recording
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                Event::MoveWindow { window, result, .. } => Some((window.window_id, result.clone())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(moves, vec![(3, Ok(())), (2, Ok(())), (7, Ok(())), (1, Ok(()))]);
+
+        let replay = ReplayWindowSystem::new(recording.clone());
+        let recorder = RecordingWindowSystem::new(Box::new(replay), dir.path().join("replayed.yaml"));
+        restore_layout(&recorder, &desired_layout, None, false).unwrap();
+        let replayed = Recording::load(&dir.path().join("replayed.yaml")).unwrap();
+
+        assert_eq!(serde_yaml::to_string(&replayed).unwrap(), serde_yaml::to_string(&recording).unwrap());
+    }
+
+    #[test]
+    fn failed_enumerations_are_recorded_and_replayed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.yaml");
+        let permission = RecordedError {
+            message: "Unable to enumerate windows.".into(),
+            exit_code: 6,
+        };
+        let failing = Recording {
+            events: vec![
+                Event::GetScreens { result: Ok(Vec::new()) },
+                Event::GetWindows {
+                    result: Err(permission.clone()),
+                },
+            ],
+        };
+
+        let recorder = RecordingWindowSystem::new(Box::new(ReplayWindowSystem::new(failing)), path.clone());
+        recorder.get_screens().unwrap();
+        let error = recorder.get_windows().unwrap_err();
+        assert_eq!(error.to_string(), "Unable to enumerate windows.");
+        // The replayed error is the same kind of error, so Layout exits the same way.
+        assert!(matches!(error.downcast_ref::<LayoutError>(), Some(LayoutError::Permission(_))));
+        assert_eq!(error::exit_code(&error), 6);
+
+        let recording = Recording::load(&path).unwrap();
+        assert!(matches!(
+            recording.events.as_slice(),
+            [Event::GetScreens { result: Ok(_) }, Event::GetWindows { result: Err(e) }] if *e == permission
+        ));
+    }
+}
@@ -0,0 +1,313 @@
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use log::trace;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::error::LayoutError;
+use crate::layout_types::{MatchingWindowInfo, Rect, ScreenInfo};
+use crate::window_system::{output_screen_id, DesktopWindow, WindowSystem};
+
+const MAGIC: &[u8] = b"i3-ipc";
+const RUN_COMMAND: u32 = 0;
+const GET_OUTPUTS: u32 = 3;
+const GET_TREE: u32 = 4;
+
+// The output that holds the scratchpad. Windows on it aren't visible, so there's no point in moving them.
+const SCRATCHPAD_OUTPUT: &str = "__i3";
+
+/// The Sway (or i3) window system, which speaks the i3 IPC protocol over `$SWAYSOCK` (or `$I3SOCK`).
+/// <br>Since tiled windows can't be put just anywhere, windows are made floating before they're moved.
+pub struct SwayWindowSystem {
+    socket_path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct IpcRect {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Output {
+    name: String,
+    active: bool,
+    rect: IpcRect,
+}
+
+#[derive(Debug, Deserialize)]
+struct WindowProperties {
+    class: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    id: i64,
+    name: Option<String>,
+    // Sway only: native Wayland windows have an app_id, XWayland windows have window_properties instead.
+    app_id: Option<String>,
+    pid: Option<i32>,
+    window_properties: Option<WindowProperties>,
+    rect: IpcRect,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    floating_nodes: Vec<Node>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommandResult {
+    success: bool,
+    error: Option<String>,
+}
+
+impl SwayWindowSystem {
+    pub fn new(socket_path: PathBuf) -> Self {
+        SwayWindowSystem { socket_path }
+    }
+
+    /// Returns the window system for the running Sway or i3 session, if there is one.
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os("SWAYSOCK")
+            .or_else(|| std::env::var_os("I3SOCK"))
+            .map(|socket_path| Self::new(socket_path.into()))
+    }
+
+    /// Sends a single IPC message, and returns the (JSON) reply.
+    fn request<T: DeserializeOwned>(&self, message_type: u32, payload: &str) -> anyhow::Result<T> {
+        let mut stream = UnixStream::connect(&self.socket_path)?;
+
+        let mut message = MAGIC.to_vec();
+        message.extend((payload.len() as u32).to_ne_bytes());
+        message.extend(message_type.to_ne_bytes());
+        message.extend(payload.as_bytes());
+        stream.write_all(&message)?;
+
+        let mut header = [0u8; 14];
+        stream.read_exact(&mut header)?;
+        if &header[..6] != MAGIC {
+            return Err(anyhow!("Invalid IPC reply from {}", self.socket_path.display()));
+        }
+        let length = u32::from_ne_bytes(header[6..10].try_into()?);
+
+        let mut reply = vec![0u8; length as usize];
+        stream.read_exact(&mut reply)?;
+
+        Ok(serde_json::from_slice(&reply)?)
+    }
+}
+
+impl WindowSystem for SwayWindowSystem {
+    fn get_screens(&self) -> anyhow::Result<Vec<ScreenInfo>> {
+        let outputs: Vec<Output> = self
+            .request(GET_OUTPUTS, "")
+            .map_err(|e| LayoutError::Backend(format!("Failed to retrieve outputs: {}", e)))?;
+
+        Ok(outputs
+            .into_iter()
+            .filter(|output| output.active)
+            .map(|output| ScreenInfo {
+                frame: output.rect.into(),
+                screen_id: output_screen_id(&output.name),
+            })
+            .collect())
+    }
+
+    fn get_windows(&self) -> anyhow::Result<Vec<DesktopWindow>> {
+        let tree: Node = self
+            .request(GET_TREE, "")
+            .map_err(|e| LayoutError::Backend(format!("Failed to retrieve the window tree: {}", e)))?;
+
+        let mut windows = Vec::new();
+        for output in tree.nodes {
+            if output.name.as_deref() != Some(SCRATCHPAD_OUTPUT) {
+                collect_windows(output, &mut windows);
+            }
+        }
+        Ok(windows)
+    }
+
+    fn move_window(&self, window: &MatchingWindowInfo, bounds: &Rect) -> anyhow::Result<()> {
+        let command = format!(
+            "[con_id={}] floating enable, resize set {} px {} px, move absolute position {} px {} px",
+            window.window_id, bounds.w, bounds.h, bounds.x, bounds.y
+        );
+        trace!("Sending command: {}", command);
+
+        let results: Vec<CommandResult> = self.request(RUN_COMMAND, &command)?;
+        match results.into_iter().find(|result| !result.success) {
+            Some(result) => Err(anyhow!("'{}' failed: {}", command, result.error.unwrap_or_default())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Walks the tree, adding every window (i.e. every container with an application in it) to `windows`.
+fn collect_windows(node: Node, windows: &mut Vec<DesktopWindow>) {
+    let owner_name = node.app_id.clone().or_else(|| {
+        node.window_properties
+            .as_ref()
+            .and_then(|properties| properties.class.clone())
+    });
+
+    if let Some(owner_name) = owner_name {
+        windows.push(DesktopWindow {
+            owner_name,
+            name: node.name.unwrap_or_default(),
+            process_id: node.pid.unwrap_or_default(),
+            window_id: node.id as u64,
+            bounds: node.rect.into(),
+        });
+        return;
+    }
+
+    for child in node.nodes.into_iter().chain(node.floating_nodes) {
+        collect_windows(child, windows);
+    }
+}
+
+impl From<IpcRect> for Rect {
+    fn from(value: IpcRect) -> Self {
+        Rect {
+            x: value.x,
+            y: value.y,
+            w: value.width,
+            h: value.height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::path::Path;
+    use std::thread;
+
+    use super::*;
+
+    /// A stand-in for Sway that replays recorded replies, and returns the messages it received.
+    fn stub_server(socket_path: &Path, requests: usize) -> thread::JoinHandle<Vec<(u32, String)>> {
+        let listener = UnixListener::bind(socket_path).unwrap();
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sway");
+
+        thread::spawn(move || {
+            let mut received = Vec::new();
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut header = [0u8; 14];
+                stream.read_exact(&mut header).unwrap();
+                let length = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+                let message_type = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+                let mut payload = vec![0u8; length as usize];
+                stream.read_exact(&mut payload).unwrap();
+
+                let reply = match message_type {
+                    GET_OUTPUTS => fs::read_to_string(fixtures.join("get_outputs.json")).unwrap(),
+                    GET_TREE => fs::read_to_string(fixtures.join("get_tree.json")).unwrap(),
+                    _ => r#"[{"success": true}, {"success": true}, {"success": true}]"#.to_string(),
+                };
+                let mut message = MAGIC.to_vec();
+                message.extend((reply.len() as u32).to_ne_bytes());
+                message.extend(message_type.to_ne_bytes());
+                message.extend(reply.as_bytes());
+                stream.write_all(&message).unwrap();
+
+                received.push((message_type, String::from_utf8(payload).unwrap()));
+            }
+            received
+        })
+    }
+
+    #[test]
+    fn enumerates_outputs_and_windows() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("sway.sock");
+        let server = stub_server(&socket_path, 2);
+        let window_system = SwayWindowSystem::new(socket_path);
+
+        let screens = window_system.get_screens().unwrap();
+        let windows = window_system.get_windows().unwrap();
+        server.join().unwrap();
+
+        assert_eq!(
+            screens.iter().map(|screen| screen.frame.clone()).collect::<Vec<_>>(),
+            vec![
+                Rect {
+                    x: 0,
+                    y: 0,
+                    w: 1920,
+                    h: 1080
+                },
+                Rect {
+                    x: 1920,
+                    y: 0,
+                    w: 2560,
+                    h: 1440
+                },
+            ]
+        );
+        assert_eq!(
+            windows,
+            vec![
+                DesktopWindow {
+                    owner_name: "foot".into(),
+                    name: "~/src/layout-rust".into(),
+                    process_id: 1234,
+                    window_id: 4,
+                    bounds: Rect {
+                        x: 0,
+                        y: 0,
+                        w: 1920,
+                        h: 1080
+                    },
+                },
+                DesktopWindow {
+                    owner_name: "firefox".into(),
+                    name: "Mozilla Firefox".into(),
+                    process_id: 2345,
+                    window_id: 7,
+                    bounds: Rect {
+                        x: 2020,
+                        y: 100,
+                        w: 1200,
+                        h: 900
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn moves_windows_with_floating_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("sway.sock");
+        let server = stub_server(&socket_path, 1);
+        let window_system = SwayWindowSystem::new(socket_path);
+        let window = MatchingWindowInfo {
+            window_id: 7,
+            ..MatchingWindowInfo::default()
+        };
+        let bounds = Rect {
+            x: 1920,
+            y: 0,
+            w: 1280,
+            h: 1440,
+        };
+
+        window_system.move_window(&window, &bounds).unwrap();
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![(
+                RUN_COMMAND,
+                "[con_id=7] floating enable, resize set 1280 px 1440 px, move absolute position 1920 px 0 px".into()
+            )]
+        );
+    }
+}
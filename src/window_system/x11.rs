@@ -0,0 +1,395 @@
+use anyhow::anyhow;
+use log::{debug, trace};
+use x11rb::connection::Connection;
+use x11rb::properties::WmClass;
+use x11rb::protocol::randr::ConnectionExt as _;
+use x11rb::protocol::xinerama::ConnectionExt as _;
+use x11rb::protocol::xproto::{AtomEnum, ClientMessageEvent, ConnectionExt as _, EventMask, Window};
+use x11rb::rust_connection::RustConnection;
+
+use crate::error::LayoutError;
+use crate::layout_types::{MatchingWindowInfo, Rect, ScreenInfo};
+use crate::window_system::{output_screen_id, DesktopWindow, WindowSystem};
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_CLIENT_LIST,
+        _NET_FRAME_EXTENTS,
+        _NET_MOVERESIZE_WINDOW,
+        _NET_WM_NAME,
+        _NET_WM_PID,
+        UTF8_STRING,
+    }
+}
+
+// _NET_MOVERESIZE_WINDOW flags: NorthWest gravity (so that x/y are the top-left of the window frame),
+// x, y, width and height present, and "pager" as the source indication so the window manager doesn't
+// second-guess us the way it might an application.
+const NORTH_WEST_GRAVITY: u32 = 1;
+const MOVERESIZE_FLAGS: u32 = NORTH_WEST_GRAVITY | 0xf << 8 | 2 << 12;
+
+/// The X11 window system, for any EWMH-compliant window manager. Screens come from RandR (falling back to
+/// Xinerama), and windows from the window manager's `_NET_CLIENT_LIST`.
+pub struct X11WindowSystem {
+    conn: RustConnection,
+    root: Window,
+    atoms: Atoms,
+}
+
+/// The size of the window manager's decorations around a window, from `_NET_FRAME_EXTENTS`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+struct FrameExtents {
+    left: i32,
+    right: i32,
+    top: i32,
+    bottom: i32,
+}
+
+impl X11WindowSystem {
+    /// Connects to the X server specified by `$DISPLAY`.
+    pub fn connect() -> anyhow::Result<Self> {
+        let (conn, screen_num) = x11rb::connect(None)
+            .map_err(|e| LayoutError::Backend(format!("Failed to connect to the X server: {}", e)))?;
+        let root = conn.setup().roots[screen_num].root;
+        let atoms = Atoms::new(&conn)?.reply()?;
+
+        Ok(X11WindowSystem { conn, root, atoms })
+    }
+
+    fn randr_screens(&self) -> anyhow::Result<Vec<ScreenInfo>> {
+        let monitors = self.conn.randr_get_monitors(self.root, true)?.reply()?.monitors;
+
+        monitors
+            .into_iter()
+            .map(|monitor| {
+                // The monitor's name is an atom, whose value can differ between sessions, so the id comes from the
+                // name itself (e.g. "DP-1").
+                let name = self.conn.get_atom_name(monitor.name)?.reply()?.name;
+                Ok(ScreenInfo {
+                    frame: Rect {
+                        x: monitor.x.into(),
+                        y: monitor.y.into(),
+                        w: monitor.width.into(),
+                        h: monitor.height.into(),
+                    },
+                    screen_id: output_screen_id(&String::from_utf8_lossy(&name)),
+                })
+            })
+            .collect()
+    }
+
+    // Xinerama screens have no names, so their ids are just their index.
+    fn xinerama_screens(&self) -> anyhow::Result<Vec<ScreenInfo>> {
+        if self.conn.xinerama_is_active()?.reply()?.state == 0 {
+            return Err(anyhow!("Xinerama is not active"));
+        }
+
+        let screens = self.conn.xinerama_query_screens()?.reply()?.screen_info;
+
+        Ok(screens
+            .into_iter()
+            .enumerate()
+            .map(|(index, screen)| ScreenInfo {
+                frame: Rect {
+                    x: screen.x_org.into(),
+                    y: screen.y_org.into(),
+                    w: screen.width.into(),
+                    h: screen.height.into(),
+                },
+                screen_id: index as u32,
+            })
+            .collect())
+    }
+
+    /// The whole X screen, for when neither RandR nor Xinerama are available.
+    fn root_screen(&self) -> anyhow::Result<Vec<ScreenInfo>> {
+        let screen = self
+            .conn
+            .setup()
+            .roots
+            .iter()
+            .find(|screen| screen.root == self.root)
+            .ok_or_else(|| LayoutError::Backend("The root window's screen is missing".into()))?;
+
+        Ok(vec![ScreenInfo {
+            frame: Rect {
+                x: 0,
+                y: 0,
+                w: screen.width_in_pixels.into(),
+                h: screen.height_in_pixels.into(),
+            },
+            screen_id: 0,
+        }])
+    }
+
+    fn client_list(&self) -> anyhow::Result<Vec<Window>> {
+        let reply = self
+            .conn
+            .get_property(false, self.root, self.atoms._NET_CLIENT_LIST, AtomEnum::WINDOW, 0, u32::MAX)?
+            .reply()?;
+
+        Ok(reply.value32().map(|windows| windows.collect()).unwrap_or_default())
+    }
+
+    fn get_window(&self, window: Window) -> anyhow::Result<DesktopWindow> {
+        let owner_name = match WmClass::get(&self.conn, window)?.reply_unchecked()? {
+            Some(wm_class) => String::from_utf8_lossy(wm_class.class()).into_owned(),
+            None => String::new(),
+        };
+
+        let geometry = self.conn.get_geometry(window)?.reply()?;
+        let origin = self.conn.translate_coordinates(window, self.root, 0, 0)?.reply()?;
+        let client_bounds = Rect {
+            x: origin.dst_x.into(),
+            y: origin.dst_y.into(),
+            w: geometry.width.into(),
+            h: geometry.height.into(),
+        };
+
+        Ok(DesktopWindow {
+            owner_name,
+            name: self.window_name(window)?,
+            process_id: self.cardinal(window, self.atoms._NET_WM_PID)?.unwrap_or_default() as i32,
+            window_id: window.into(),
+            bounds: self.frame_extents(window)?.outer_bounds(&client_bounds),
+        })
+    }
+
+    /// Returns `_NET_WM_NAME`, or the old-school `WM_NAME` if the window doesn't have one.
+    fn window_name(&self, window: Window) -> anyhow::Result<String> {
+        let reply = self
+            .conn
+            .get_property(false, window, self.atoms._NET_WM_NAME, self.atoms.UTF8_STRING, 0, u32::MAX)?
+            .reply()?;
+        if !reply.value.is_empty() {
+            return Ok(String::from_utf8_lossy(&reply.value).into_owned());
+        }
+
+        let reply = self
+            .conn
+            .get_property(false, window, AtomEnum::WM_NAME, AtomEnum::STRING, 0, u32::MAX)?
+            .reply()?;
+        Ok(String::from_utf8_lossy(&reply.value).into_owned())
+    }
+
+    fn cardinal(&self, window: Window, property: u32) -> anyhow::Result<Option<u32>> {
+        let reply = self
+            .conn
+            .get_property(false, window, property, AtomEnum::CARDINAL, 0, 1)?
+            .reply()?;
+
+        Ok(reply.value32().and_then(|mut values| values.next()))
+    }
+
+    fn frame_extents(&self, window: Window) -> anyhow::Result<FrameExtents> {
+        let reply = self
+            .conn
+            .get_property(false, window, self.atoms._NET_FRAME_EXTENTS, AtomEnum::CARDINAL, 0, 4)?
+            .reply()?;
+
+        let extents: Vec<u32> = reply.value32().map(|values| values.collect()).unwrap_or_default();
+        Ok(match extents[..] {
+            [left, right, top, bottom] => FrameExtents {
+                left: left as i32,
+                right: right as i32,
+                top: top as i32,
+                bottom: bottom as i32,
+            },
+            _ => FrameExtents::default(),
+        })
+    }
+}
+
+impl WindowSystem for X11WindowSystem {
+    fn get_screens(&self) -> anyhow::Result<Vec<ScreenInfo>> {
+        let screens = self.randr_screens().or_else(|e| {
+            debug!("RandR screen enumeration failed ({}), trying Xinerama", e);
+            self.xinerama_screens()
+        });
+
+        match screens {
+            Ok(screens) if !screens.is_empty() => Ok(screens),
+            Ok(_) => self.root_screen(),
+            Err(e) => {
+                debug!("Xinerama screen enumeration failed ({}), using the root window", e);
+                self.root_screen()
+            }
+        }
+    }
+
+    fn get_windows(&self) -> anyhow::Result<Vec<DesktopWindow>> {
+        let client_list = self
+            .client_list()
+            .map_err(|e| LayoutError::Backend(format!("Failed to retrieve _NET_CLIENT_LIST: {}", e)))?;
+
+        Ok(client_list
+            .into_iter()
+            .filter_map(|window| match self.get_window(window) {
+                Ok(desktop_window) => Some(desktop_window),
+                Err(e) => {
+                    // Most likely the window was closed while we were looking at it.
+                    trace!("Skipping window {:#x}: {}", window, e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    fn move_window(&self, window: &MatchingWindowInfo, bounds: &Rect) -> anyhow::Result<()> {
+        let extents = self.frame_extents(window.window_id as Window)?;
+        let event = ClientMessageEvent::new(
+            32,
+            window.window_id as Window,
+            self.atoms._NET_MOVERESIZE_WINDOW,
+            moveresize_data(bounds, &extents),
+        );
+
+        self.conn.send_event(
+            false,
+            self.root,
+            EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
+            event,
+        )?;
+        self.conn.flush()?;
+
+        Ok(())
+    }
+}
+
+impl FrameExtents {
+    /// Returns the bounds of the window including its frame, given the bounds of the client area.
+    fn outer_bounds(&self, client_bounds: &Rect) -> Rect {
+        Rect {
+            x: client_bounds.x - self.left,
+            y: client_bounds.y - self.top,
+            w: client_bounds.w + self.left + self.right,
+            h: client_bounds.h + self.top + self.bottom,
+        }
+    }
+}
+
+/// Builds the `_NET_MOVERESIZE_WINDOW` message data. The position is that of the frame (thanks to NorthWest
+/// gravity), but the size is that of the client area, so the frame has to be subtracted from it.
+fn moveresize_data(bounds: &Rect, extents: &FrameExtents) -> [u32; 5] {
+    [
+        MOVERESIZE_FLAGS,
+        bounds.x as u32,
+        bounds.y as u32,
+        (bounds.w - extents.left - extents.right).max(1) as u32,
+        (bounds.h - extents.top - extents.bottom).max(1) as u32,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    use x11rb::protocol::xproto::{CreateWindowAux, PropMode, WindowClass};
+    use x11rb::wrapper::ConnectionExt as _;
+    use x11rb::COPY_DEPTH_FROM_PARENT;
+
+    use super::*;
+
+    const EXTENTS: FrameExtents = FrameExtents {
+        left: 2,
+        right: 2,
+        top: 30,
+        bottom: 4,
+    };
+
+    #[test]
+    fn frame_extents_are_added_to_client_bounds() {
+        let client_bounds = Rect {
+            x: 102,
+            y: 130,
+            w: 800,
+            h: 600,
+        };
+
+        assert_eq!(
+            EXTENTS.outer_bounds(&client_bounds),
+            Rect {
+                x: 100,
+                y: 100,
+                w: 804,
+                h: 634
+            }
+        );
+    }
+
+    #[test]
+    fn moveresize_uses_frame_position_and_client_size() {
+        let bounds = Rect {
+            x: -10,
+            y: 100,
+            w: 804,
+            h: 634,
+        };
+
+        let data = moveresize_data(&bounds, &EXTENTS);
+
+        assert_eq!(data, [0x2f01, -10i32 as u32, 100, 800, 600]);
+    }
+
+    /// Runs against a real X server, e.g.: `Xvfb :99 & DISPLAY=:99 openbox & DISPLAY=:99 cargo test -- --ignored`
+    #[test]
+    #[ignore = "needs an X server with an EWMH window manager"]
+    fn moves_a_real_window() {
+        let window_system = X11WindowSystem::connect().unwrap();
+        let conn = &window_system.conn;
+
+        let window = conn.generate_id().unwrap();
+        conn.create_window(
+            COPY_DEPTH_FROM_PARENT,
+            window,
+            window_system.root,
+            0,
+            0,
+            300,
+            200,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::new(),
+        )
+        .unwrap();
+        conn.change_property8(PropMode::REPLACE, window, AtomEnum::WM_CLASS, AtomEnum::STRING, b"layout\0LayoutTest\0")
+            .unwrap();
+        conn.change_property8(
+            PropMode::REPLACE,
+            window,
+            window_system.atoms._NET_WM_NAME,
+            window_system.atoms.UTF8_STRING,
+            "Layout – test".as_bytes(),
+        )
+        .unwrap();
+        conn.map_window(window).unwrap();
+        conn.flush().unwrap();
+        sleep(Duration::from_millis(500));
+
+        let desktop_window = window_system
+            .get_windows()
+            .unwrap()
+            .into_iter()
+            .find(|desktop_window| desktop_window.window_id == u64::from(window))
+            .expect("test window not in _NET_CLIENT_LIST");
+        assert_eq!(desktop_window.owner_name, "LayoutTest");
+        assert_eq!(desktop_window.name, "Layout – test");
+
+        let bounds = Rect {
+            x: 50,
+            y: 60,
+            w: 400,
+            h: 300,
+        };
+        let matching_window = MatchingWindowInfo {
+            window_id: window.into(),
+            ..MatchingWindowInfo::default()
+        };
+        window_system.move_window(&matching_window, &bounds).unwrap();
+        sleep(Duration::from_millis(500));
+
+        assert!(window_system.get_window(window).unwrap().bounds.is_close(&bounds));
+    }
+}